@@ -0,0 +1,7 @@
+#pragma once
+
+#include <stddef.h>
+#include <stdint.h>
+
+void serial_write(const uint8_t *data, size_t len);
+size_t serial_read(uint8_t *data, size_t len);
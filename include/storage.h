@@ -0,0 +1,6 @@
+#pragma once
+
+#include <stddef.h>
+
+int storage_write(const char *key, const void *data, size_t len);
+int storage_read(const char *key, void *data, size_t len);
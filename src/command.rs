@@ -0,0 +1,74 @@
+use crate::param::Setting;
+use crate::state::State;
+
+/* Everything an input (encoder, serial console, BLE, ...) can ask for */
+#[derive(Copy, Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum Command {
+    /* Starts a session, or resumes a paused one */
+    Start,
+    Stop,
+    /* Holds stimulation while the session carries on, until the next Start */
+    Pause,
+    Toggle,
+    /* Everything off, from any screen and in any mode */
+    EmergencyStop,
+    /* Allows starting again after an emergency stop */
+    AcknowledgeStop,
+    /* Set a setting to a value */
+    Set(Setting, u32),
+    /* Move a setting by a number of menu steps, toggles and choices wrap around */
+    Adjust(Setting, i32),
+    /* Toggle a switch or move a choice to its next option */
+    Cycle(Setting),
+    /* Stimulate by hand while no session is running */
+    ManualStim(bool),
+    Calibrate,
+    /* Keyholder mode, with the code that unlocks it */
+    Lock(u32),
+    Unlock(u32),
+}
+
+/* While locked only starting or pausing a session, the emergency stop and unlocking get through */
+fn allowed_while_locked(state: &State, command: Command) -> bool {
+    use Command::*;
+    match command {
+        Start | Pause | EmergencyStop | AcknowledgeStop | Unlock(_) => true,
+        Toggle => !state.running,
+        _ => false,
+    }
+}
+
+/* The single place inputs change State, every change is recorded in the event log,
+ * which the display, flash log and telemetry follow */
+pub fn dispatch(state: &mut State, command: Command) -> bool {
+    use Command::*;
+    if state.lock.is_locked() && !allowed_while_locked(state, command) {
+        return false;
+    }
+    /* Nothing starts again until the emergency stop was acknowledged */
+    if state.emergency_stopped && matches!(command, Start | Toggle | ManualStim(true)) {
+        return false;
+    }
+    match command {
+        Start if state.paused => state.resume(),
+        Start | Stop => {
+            if state.running != matches!(command, Start) {
+                state.toggle();
+            }
+        }
+        Pause => state.pause(),
+        Toggle => state.toggle(),
+        EmergencyStop => state.emergency_stop(),
+        AcknowledgeStop => return state.acknowledge_stop(),
+        Set(setting, value) => return state.set_setting(setting, value),
+        Adjust(setting, steps) => return state.adjust_setting(setting, steps),
+        Cycle(setting) => return state.cycle_setting(setting),
+        ManualStim(true) => state.start_stim_manual(),
+        ManualStim(false) => state.stop_stim_manual(),
+        Calibrate => state.request_calibration(),
+        Lock(code) => return state.lock(code),
+        Unlock(code) => return state.unlock(code),
+    }
+    true
+}
@@ -0,0 +1,367 @@
+use core::fmt::{self, Write};
+use heapless::String;
+
+use crate::command::{dispatch, Command};
+use crate::lock::MAX_CODE;
+use crate::param::{ParamKind, Setting, PARAMS};
+use crate::profile::PROFILES;
+use crate::state::State;
+
+const LINE_LEN: usize = 64;
+
+#[derive(Copy, Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum ConsoleCommand<'a> {
+    Help,
+    Get(Option<&'a str>),
+    Set(&'a str, u32),
+    Start,
+    Stop,
+    EmergencyStop,
+    Acknowledge,
+    Intensity(u32),
+    ProfileLoad(&'a str),
+    Stats,
+    LogDump,
+    Calibrate,
+    Lock(u32),
+    Unlock(u32),
+}
+
+#[derive(Copy, Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    InvalidNumber,
+    TooManyArguments,
+}
+
+impl ParseError {
+    pub fn message(self) -> &'static str {
+        use ParseError::*;
+        match self {
+            Empty => "empty line",
+            UnknownCommand => "unknown command, try help",
+            MissingArgument => "missing argument",
+            InvalidNumber => "invalid number",
+            TooManyArguments => "too many arguments",
+        }
+    }
+}
+
+fn number(word: Option<&str>) -> Result<u32, ParseError> {
+    word.ok_or(ParseError::MissingArgument)?
+        .parse()
+        .map_err(|_| ParseError::InvalidNumber)
+}
+
+pub fn parse(line: &str) -> Result<ConsoleCommand<'_>, ParseError> {
+    use ConsoleCommand::*;
+    let mut words = line.split_ascii_whitespace();
+    let command = match words.next().ok_or(ParseError::Empty)? {
+        "help" => Help,
+        "get" => Get(words.next()),
+        "set" => {
+            let name = words.next().ok_or(ParseError::MissingArgument)?;
+            Set(name, number(words.next())?)
+        }
+        "start" => Start,
+        "stop" => Stop,
+        "estop" => EmergencyStop,
+        "ack" => Acknowledge,
+        "intensity" => Intensity(number(words.next())?),
+        "profile" => match words.next() {
+            Some("load") => ProfileLoad(words.next().ok_or(ParseError::MissingArgument)?),
+            Some(_) => return Err(ParseError::UnknownCommand),
+            None => return Err(ParseError::MissingArgument),
+        },
+        "stats" => Stats,
+        "log" => match words.next() {
+            Some("dump") => LogDump,
+            Some(_) => return Err(ParseError::UnknownCommand),
+            None => return Err(ParseError::MissingArgument),
+        },
+        "calibrate" => Calibrate,
+        "lock" => Lock(number(words.next())?),
+        "unlock" => Unlock(number(words.next())?),
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    if words.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+    Ok(command)
+}
+
+/* Why dispatch turned a command down */
+fn write_refusal<W: Write>(w: &mut W, state: &State) -> fmt::Result {
+    match state.lock.is_locked() {
+        true => writeln!(w, "err: locked"),
+        false => writeln!(w, "err: emergency stop, ack first"),
+    }
+}
+
+fn write_setting<W: Write>(w: &mut W, state: &State, setting: Setting) -> fmt::Result {
+    let value = state.get_setting(setting);
+    match setting.param().kind {
+        ParamKind::Choice(option) => {
+            writeln!(w, "{}={} ({})", setting.name(), value, option(value))
+        }
+        _ => writeln!(w, "{}={}", setting.name(), value),
+    }
+}
+
+fn find_profile(name: &str) -> Option<usize> {
+    match name.parse::<usize>() {
+        Ok(index) if index < PROFILES.len() => Some(index),
+        Ok(_) => None,
+        Err(_) => PROFILES
+            .iter()
+            .position(|profile| profile.name.eq_ignore_ascii_case(name)),
+    }
+}
+
+/* Turns a console command into the same commands the menu sends */
+pub fn execute<W: Write>(command: ConsoleCommand, state: &mut State, w: &mut W) -> fmt::Result {
+    use ConsoleCommand::*;
+    match command {
+        Help => writeln!(
+            w,
+            "get [name], set <name> <value>, start, stop, estop, ack, intensity <n>, \
+             profile load <name|n>, stats, log dump, calibrate, lock <code>, unlock <code>"
+        ),
+        Get(None) => {
+            for param in PARAMS.iter() {
+                write_setting(w, state, param.setting)?;
+            }
+            Ok(())
+        }
+        Get(Some(name)) => match Setting::from_name(name) {
+            Some(setting) => write_setting(w, state, setting),
+            None => writeln!(w, "err: unknown parameter {}", name),
+        },
+        Set(_, _) if state.lock.is_locked() => writeln!(w, "err: locked"),
+        Set(name, value) => match Setting::from_name(name) {
+            Some(setting) if dispatch(state, Command::Set(setting, value)) => {
+                write_setting(w, state, setting)
+            }
+            Some(_) => writeln!(w, "err: invalid value {}", value),
+            None => writeln!(w, "err: unknown parameter {}", name),
+        },
+        Start => match dispatch(state, Command::Start) {
+            true => writeln!(w, "running={}", state.running as u32),
+            false => write_refusal(w, state),
+        },
+        Stop => match dispatch(state, Command::Stop) {
+            true => writeln!(w, "running={}", state.running as u32),
+            false => write_refusal(w, state),
+        },
+        EmergencyStop => match dispatch(state, Command::EmergencyStop) {
+            true => writeln!(w, "emergency stop, ack to allow starting again"),
+            false => write_refusal(w, state),
+        },
+        Acknowledge => match dispatch(state, Command::AcknowledgeStop) {
+            true => writeln!(w, "ok"),
+            false => writeln!(w, "err: no emergency stop"),
+        },
+        Intensity(value) => match dispatch(state, Command::Set(Setting::Intensity, value)) {
+            true => write_setting(w, state, Setting::Intensity),
+            false => write_refusal(w, state),
+        },
+        ProfileLoad(name) => match find_profile(name) {
+            Some(index) => match dispatch(state, Command::Set(Setting::Profile, index as u32)) {
+                true => writeln!(w, "profile={}", PROFILES[index].name),
+                false => write_refusal(w, state),
+            },
+            None => writeln!(w, "err: unknown profile {}", name),
+        },
+        Stats => {
+            for line in 0..state.stats.summary_lines() {
+                state.stats.write_summary_line(line, w)?;
+                writeln!(w)?;
+            }
+            Ok(())
+        }
+        LogDump => state.log.dump(w),
+        Calibrate => match dispatch(state, Command::Calibrate) {
+            true => writeln!(w, "ok"),
+            false => write_refusal(w, state),
+        },
+        Lock(code) => match dispatch(state, Command::Lock(code)) {
+            true => writeln!(w, "locked"),
+            false => writeln!(w, "err: already locked or code above {}", MAX_CODE),
+        },
+        /* A remote keyholder can unlock without touching the device */
+        Unlock(code) => match dispatch(state, Command::Unlock(code)) {
+            true => writeln!(w, "unlocked"),
+            false => writeln!(w, "err: not unlocked"),
+        },
+    }
+}
+
+/* Collects received bytes into lines and executes them */
+pub struct Console {
+    line: String<LINE_LEN>,
+    overflow: bool,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            line: String::new(),
+            overflow: false,
+        }
+    }
+
+    pub fn feed<W: Write>(&mut self, bytes: &[u8], state: &mut State, w: &mut W) -> fmt::Result {
+        for &byte in bytes {
+            match byte {
+                b'\r' | b'\n' => {
+                    if self.overflow {
+                        writeln!(w, "err: line too long")?;
+                    } else if !self.line.is_empty() {
+                        match parse(&self.line) {
+                            Ok(command) => execute(command, state, w)?,
+                            Err(err) => writeln!(w, "err: {}", err.message())?,
+                        }
+                    }
+                    self.line.clear();
+                    self.overflow = false;
+                }
+                byte if byte.is_ascii_graphic() || byte == b' ' => {
+                    self.overflow |= self.line.push(byte as char).is_err();
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ConsoleCommand::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("help"), Ok(Help));
+        assert_eq!(parse("get"), Ok(Get(None)));
+        assert_eq!(parse("get cooldown"), Ok(Get(Some("cooldown"))));
+        assert_eq!(parse("set grace 1500"), Ok(Set("grace", 1500)));
+        assert_eq!(parse("start"), Ok(Start));
+        assert_eq!(parse("stop"), Ok(Stop));
+        assert_eq!(parse("estop"), Ok(EmergencyStop));
+        assert_eq!(parse("ack"), Ok(Acknowledge));
+        assert_eq!(parse("intensity 12"), Ok(Intensity(12)));
+        assert_eq!(parse("profile load Waves"), Ok(ProfileLoad("Waves")));
+        assert_eq!(parse("stats"), Ok(Stats));
+        assert_eq!(parse("log dump"), Ok(LogDump));
+        assert_eq!(parse("calibrate"), Ok(Calibrate));
+        assert_eq!(parse("lock 1234"), Ok(Lock(1234)));
+        assert_eq!(parse("unlock 1234"), Ok(Unlock(1234)));
+    }
+
+    #[test]
+    fn ignores_extra_whitespace() {
+        assert_eq!(parse("  set \t grace   1500 "), Ok(Set("grace", 1500)));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("   "), Err(ParseError::Empty));
+        assert_eq!(parse("reboot"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("set grace"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("set grace -5"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("intensity high"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("profile"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("profile save x"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("log"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("stop now"), Err(ParseError::TooManyArguments));
+    }
+
+    #[test]
+    fn set_uses_menu_limits() {
+        let mut state = State::new();
+        let mut out = String::<256>::new();
+        execute(Set("intensity", 99), &mut state, &mut out).unwrap();
+        assert_eq!(out, "intensity=20\n");
+        assert!(state.set_setting(Setting::Tease, 1));
+        assert!(state.tease_mode);
+        assert!(!state.set_setting(Setting::Tease, 2));
+        assert!(state.set_setting(Setting::CooldownPolicy, 2));
+        assert_eq!(state.get_setting(Setting::CooldownPolicy), 2);
+        assert!(!state.set_setting(Setting::CooldownPolicy, 7));
+    }
+
+    #[test]
+    fn lock_blocks_settings_and_stop() {
+        let mut state = State::new();
+        let mut out = String::<256>::new();
+        for line in [
+            "lock 1234",
+            "set grace 1500",
+            "start",
+            "stop",
+            "intensity 5",
+            "profile load 1",
+            "calibrate",
+            "unlock 1111",
+            "unlock 1234",
+        ] {
+            execute(parse(line).unwrap(), &mut state, &mut out).unwrap();
+        }
+        assert_eq!(
+            out,
+            "locked\nerr: locked\nrunning=1\nerr: locked\nerr: locked\nerr: locked\n\
+             err: locked\nerr: not unlocked\nerr: not unlocked\n"
+        );
+        assert_eq!(state.get_setting(Setting::Grace), 3000);
+        assert_eq!(state.get_setting(Setting::Intensity), 10);
+        assert!(!state.take_calibration_request());
+
+        /* Retry after the wrong code is allowed again a while later */
+        out.clear();
+        state.cur_time_ms = 10_000;
+        execute(Unlock(1234), &mut state, &mut out).unwrap();
+        execute(Stop, &mut state, &mut out).unwrap();
+        assert_eq!(out, "unlocked\nrunning=0\n");
+    }
+
+    #[test]
+    fn feeds_lines() {
+        let mut state = State::new();
+        let mut console = Console::new();
+        let mut out = String::<256>::new();
+        console.feed(b"get gra", &mut state, &mut out).unwrap();
+        assert_eq!(out, "");
+        console
+            .feed(b"ce\r\nbogus\n", &mut state, &mut out)
+            .unwrap();
+        assert_eq!(out, "grace=3000\nerr: unknown command, try help\n");
+
+        out.clear();
+        console
+            .feed(&[b'x'; LINE_LEN + 1], &mut state, &mut out)
+            .unwrap();
+        console.feed(b"\nstart\n", &mut state, &mut out).unwrap();
+        assert_eq!(out, "err: line too long\nrunning=1\n");
+        assert!(state.running);
+    }
+
+    #[test]
+    fn emergency_stop_refuses_start() {
+        let mut state = State::new();
+        let mut out = String::<256>::new();
+        for line in ["estop", "start", "ack", "start"] {
+            execute(parse(line).unwrap(), &mut state, &mut out).unwrap();
+        }
+        assert_eq!(
+            out,
+            "emergency stop, ack to allow starting again\nerr: emergency stop, ack first\n\
+             ok\nrunning=1\n"
+        );
+    }
+}
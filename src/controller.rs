@@ -0,0 +1,244 @@
+use embedded_hal::digital::v2::InputPin;
+use log::info;
+
+use crate::command::{dispatch, Command};
+use crate::encoder::Encoder;
+use crate::eventlog::EventKind;
+use crate::history::{HistoryResult, Nogasm};
+use crate::insertion::{Insertion, InsertionResult};
+use crate::menu::{Menu, COARSE_STEPS};
+use crate::state::State;
+use crate::switch::{DebouncedSwitch, SwitchEvent};
+
+/* The part of the main loop that does not touch the hardware, so the firmware and the
+ * simulator run the same steps. Inputs come in with the time they were read at */
+pub struct Controller<SWITCH> {
+    pub menu: Menu,
+    pub state: State,
+    pub history: Nogasm<4>,
+    pub insertion: Insertion,
+    pub encoder: Encoder,
+    pub switch: DebouncedSwitch<SWITCH>,
+}
+
+impl<SWITCH> Controller<SWITCH>
+where
+    SWITCH: InputPin,
+{
+    pub fn new(state: State, menu: Menu, switch: DebouncedSwitch<SWITCH>) -> Controller<SWITCH> {
+        Controller {
+            menu,
+            state,
+            history: Nogasm::new(),
+            insertion: Insertion::new(),
+            encoder: Encoder::new(),
+            switch,
+        }
+    }
+
+    /* Detents turned since the last call, the menu turns them into commands */
+    pub fn turn(&mut self, detents: i32, time: u32) {
+        if detents == 0 {
+            return;
+        }
+        /* Turning while holding the switch takes coarse steps, and is not a click */
+        let held = self.switch.is_held();
+        let steps = self.encoder.steps(
+            detents,
+            time,
+            self.state.encoder_inverted,
+            self.state.encoder_acceleration && !held,
+        );
+        let steps = match held {
+            true => {
+                self.switch.consume();
+                steps.saturating_mul(COARSE_STEPS)
+            }
+            false => steps,
+        };
+        if let Some(command) = self.menu.turn(&self.state, steps) {
+            dispatch(&mut self.state, command);
+        }
+    }
+
+    pub fn poll_switch(&mut self, now_us: u64) {
+        while let Some(event) = self.switch.poll(now_us) {
+            let command = match event {
+                SwitchEvent::Click => self.menu.click(&self.state),
+                SwitchEvent::DoubleClick => self.menu.home(),
+                SwitchEvent::LongPress => Some(Command::EmergencyStop),
+                _ => None,
+            };
+            if let Some(command) = command {
+                dispatch(&mut self.state, command);
+            }
+        }
+    }
+
+    /* Runs timers, limits and programs up to `time`, after the inputs were handled */
+    pub fn update(&mut self, time: u32) {
+        if self.state.take_calibration_request() {
+            info!("Calibrating sensor baseline");
+            self.history.calibrate();
+            self.insertion.calibrate();
+        }
+        self.state.cur_time_ms = time;
+        self.state.update();
+        self.menu.update(&self.state);
+    }
+
+    /* Without a session the sensor only matters to notice it being inserted, otherwise
+     * manual control works without it */
+    pub fn wants_sensor(&self) -> bool {
+        self.state.running || self.state.auto_start
+    }
+
+    /* A reading of the sensor, None if it failed. True if the detector got it */
+    pub fn sensor(&mut self, val: Option<u32>) -> bool {
+        self.state.sensor_read(val.is_some());
+        let Some(val) = val else {
+            return false;
+        };
+        let time = self.state.cur_time_ms;
+        /* Start or pause the session when the sensor is inserted or removed */
+        if self.state.auto_start {
+            let sensor = match self.insertion.add(val, time, &self.state) {
+                InsertionResult::Inserted => Some((EventKind::SensorInserted, Command::Start)),
+                InsertionResult::Removed => Some((EventKind::SensorRemoved, Command::Pause)),
+                InsertionResult::None => None,
+            };
+            if let Some((event, command)) = sensor {
+                self.state.log_event(event);
+                dispatch(&mut self.state, command);
+            }
+        }
+        if !self.state.running {
+            return false;
+        }
+
+        let res = self.history.add(val, time, &mut self.state);
+        if self.state.telemetry_interval == 0 {
+            info!(
+                "V:{}, A:{}, R:{}",
+                val,
+                self.history.get_area(),
+                self.history.get_rhythm()
+            );
+        }
+        match res {
+            /* The session may have been ended by a program */
+            _ if !self.state.running => {}
+            HistoryResult::Stop => self.state.stop_stim(),
+            HistoryResult::Resume => self.state.start_stim(),
+        }
+        true
+    }
+
+    /* Intensity the toy should run at */
+    pub fn output(&self) -> u8 {
+        self.state.get_cur_intensity()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::param::Setting;
+    use crate::switch::SwitchTimings;
+    use core::convert::Infallible;
+
+    pub(crate) struct Released;
+
+    impl InputPin for Released {
+        type Error = Infallible;
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(true)
+        }
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(false)
+        }
+    }
+
+    pub(crate) fn controller() -> Controller<Released> {
+        let switch = DebouncedSwitch::new(Released, SwitchTimings::default());
+        Controller::new(State::new(), Menu::default(), switch)
+    }
+
+    /* Feeds a sample every 25ms from `from` up to `to`, until `done` holds */
+    fn feed(
+        controller: &mut Controller<Released>,
+        val: u32,
+        from: u32,
+        to: u32,
+        done: impl Fn(&State) -> bool,
+    ) -> Option<u32> {
+        for time in (from..to).step_by(25) {
+            controller.update(time);
+            if controller.wants_sensor() {
+                controller.sensor(Some(val));
+            }
+            if done(&controller.state) {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn insertion_starts_and_pauses_session() {
+        let mut controller = controller();
+        assert!(!controller.wants_sensor());
+        controller.state.set_setting(Setting::AutoStart, 1);
+        /* The pressure steps would read as edges otherwise */
+        controller
+            .state
+            .set_setting(Setting::Sensitivity, 1_000_000);
+        assert!(controller.wants_sensor());
+
+        feed(&mut controller, 1_000_000, 0, 2_000, |_| false);
+        let started = feed(&mut controller, 1_100_000, 2_000, 20_000, |state| {
+            state.running
+        });
+        assert!(started.unwrap() >= 2_000 + controller.state.insertion_settle_time);
+        assert!(controller.state.stimulating);
+
+        let paused = feed(&mut controller, 1_000_000, 20_000, 40_000, |state| {
+            state.paused
+        });
+        assert!(paused.is_some() && controller.state.running);
+        assert_eq!(controller.output(), 0);
+
+        let resumed = feed(&mut controller, 1_100_000, 40_000, 60_000, |state| {
+            !state.paused
+        });
+        assert!(resumed.is_some() && controller.state.stimulating);
+
+        let kinds: heapless::Vec<&str, 16> = controller
+            .state
+            .log
+            .newer_than(0)
+            .map(|event| event.kind.name())
+            .collect();
+        assert_eq!(
+            kinds
+                .iter()
+                .filter(|&&kind| kind == "sensor_inserted")
+                .count(),
+            2
+        );
+        assert!(kinds.contains(&"sensor_removed"));
+    }
+
+    #[test]
+    fn detector_only_runs_in_a_session() {
+        let mut controller = controller();
+        controller.update(1_000);
+        assert!(!controller.sensor(Some(1_000_000)));
+        assert!(!controller.sensor(None));
+        let last = controller.state.log.newer_than(0).last().unwrap();
+        assert_eq!(last.kind.name(), "sensor_fault");
+
+        dispatch(&mut controller.state, Command::Start);
+        assert!(controller.sensor(Some(1_000_000)));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::state::State;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CooldownPolicy {
+    Fixed,
+    Linear,
+    Exponential,
+}
+
+impl CooldownPolicy {
+    pub fn from_index(index: u32) -> CooldownPolicy {
+        use CooldownPolicy::*;
+        match index {
+            1 => Linear,
+            2 => Exponential,
+            _ => Fixed,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use CooldownPolicy::*;
+        match self {
+            Fixed => "fixed",
+            Linear => "linear",
+            Exponential => "exponential",
+        }
+    }
+}
+
+pub struct Cooldown {
+    pub edges: u32,
+    last_edge: u32,
+}
+
+impl Cooldown {
+    pub fn new() -> Cooldown {
+        Cooldown {
+            edges: 0,
+            last_edge: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.edges = 0;
+    }
+
+    pub fn edge(&mut self, time: u32, reset_time: u32) {
+        self.edges = self.edges_at(time, reset_time) + 1;
+        self.last_edge = time;
+    }
+
+    /* Edges that still count towards escalation, forgetting them after a calm period.
+     * A reset time of 0 never forgets them */
+    fn edges_at(&self, time: u32, reset_time: u32) -> u32 {
+        if reset_time > 0 && time.wrapping_sub(self.last_edge) >= reset_time {
+            return 0;
+        }
+        self.edges
+    }
+
+    pub fn next_duration(&self, time: u32, state: &State) -> u32 {
+        use CooldownPolicy::*;
+        let base = state.cooldown_time;
+        let edges = self.edges_at(time, state.cooldown_reset_time);
+        let max = state.cooldown_max.max(base);
+        match state.cooldown_policy {
+            Fixed => base,
+            Linear => base
+                .saturating_add(edges.saturating_mul(state.cooldown_increment))
+                .min(max),
+            Exponential => {
+                let mut duration = base as u64;
+                for _ in 0..edges {
+                    if duration >= max as u64 {
+                        break;
+                    }
+                    duration = duration * state.cooldown_factor as u64 / 100;
+                }
+                duration.min(max as u64) as u32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(policy: CooldownPolicy) -> State {
+        let mut state = State::new();
+        state.cooldown_policy = policy;
+        state.cooldown_time = 10_000;
+        state.cooldown_increment = 5_000;
+        state.cooldown_factor = 200;
+        state.cooldown_max = 60_000;
+        state.cooldown_reset_time = 120_000;
+        state
+    }
+
+    /* Durations of cooldowns for edges `gap` ms apart */
+    fn durations(state: &State, gap: u32, count: usize) -> heapless::Vec<u32, 8> {
+        let mut cooldown = Cooldown::new();
+        let mut time = 1_000;
+        let mut durations = heapless::Vec::new();
+        for _ in 0..count {
+            durations.push(cooldown.next_duration(time, state)).unwrap();
+            cooldown.edge(time, state.cooldown_reset_time);
+            time += gap;
+        }
+        durations
+    }
+
+    #[test]
+    fn linear_escalation() {
+        let state = state(CooldownPolicy::Linear);
+        assert_eq!(
+            durations(&state, 30_000, 5),
+            [10_000, 15_000, 20_000, 25_000, 30_000]
+        );
+        /* Calm periods longer than the reset time start over */
+        assert_eq!(durations(&state, 120_000, 3), [10_000, 10_000, 10_000]);
+    }
+
+    #[test]
+    fn exponential_escalation() {
+        let state = state(CooldownPolicy::Exponential);
+        assert_eq!(
+            durations(&state, 30_000, 5),
+            [10_000, 20_000, 40_000, 60_000, 60_000]
+        );
+        assert_eq!(durations(&state, 120_000, 3), [10_000, 10_000, 10_000]);
+    }
+
+    #[test]
+    fn zero_reset_time_never_resets() {
+        let mut state = state(CooldownPolicy::Linear);
+        state.cooldown_reset_time = 0;
+        assert_eq!(durations(&state, 600_000, 3), [10_000, 15_000, 20_000]);
+        state.cooldown_policy = CooldownPolicy::Exponential;
+        assert_eq!(durations(&state, 600_000, 3), [10_000, 20_000, 40_000]);
+
+        let mut cooldown = Cooldown::new();
+        cooldown.edge(1_000, 0);
+        cooldown.reset();
+        assert_eq!(cooldown.next_duration(2_000, &state), 10_000);
+    }
+}
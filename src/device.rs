@@ -0,0 +1,301 @@
+extern crate alloc;
+use core::cell::{Cell, RefCell};
+
+use alloc::boxed::Box;
+use critical_section::Mutex;
+use esp_backtrace as _;
+use hal::i2c::I2C;
+use hal::{clock::ClockControl, peripherals::Peripherals, prelude::*, Rtc};
+use log::info;
+// use panic_halt as _;
+
+use rotary_encoder_embedded::RotaryEncoder;
+
+use crate::ble::{ble_get_name, ble_is_connected};
+use crate::console::Console;
+use crate::controller::Controller;
+use crate::display::OLEDDisplay;
+use crate::eventlog::EventKind;
+use crate::menu::Menu;
+use crate::state::State;
+use crate::switch::{DebouncedSwitch, SwitchTimings};
+use crate::telemetry::{Record, Telemetry, MAX_FRAME};
+use crate::{h710, panel, serial, storage};
+
+#[global_allocator]
+static ALLOCATOR: esp_alloc::EspHeap = esp_alloc::EspHeap::empty();
+
+#[no_mangle]
+pub extern "C" fn rs_init_heap(heap_start: *mut u8, heap_size: cty::size_t) {
+    unsafe {
+        ALLOCATOR.init(heap_start as *mut u8, heap_size.into());
+    }
+}
+
+static ENCODER: Mutex<
+    RefCell<
+        Option<
+            RotaryEncoder<
+                rotary_encoder_embedded::standard::StandardMode,
+                hal::gpio::GpioPin<hal::gpio::Input<hal::gpio::PullUp>, 4>,
+                hal::gpio::GpioPin<hal::gpio::Input<hal::gpio::PullUp>, 15>,
+            >,
+        >,
+    >,
+> = Mutex::new(RefCell::new(None));
+
+/* Detents turned since loop_once last looked, clockwise is positive */
+static ENCODER_STEPS: Mutex<Cell<i32>> = Mutex::new(Cell::new(0));
+
+#[repr(C)]
+pub struct RustState<'a> {
+    controller: Box<Controller<hal::gpio::GpioPin<hal::gpio::Input<hal::gpio::PullUp>, 5>>>,
+    display: Box<OLEDDisplay<panel::OLEDPanel<'a>>>,
+    telemetry: Box<Telemetry>,
+    console: Box<Console>,
+    h710: Box<
+        h710::H710<
+            hal::gpio::GpioPin<hal::gpio::Input<hal::gpio::PullUp>, 16>,
+            hal::gpio::GpioPin<hal::gpio::Output<hal::gpio::PushPull>, 17>,
+            hal::Delay,
+        >,
+    >,
+    rtc: Box<Rtc<'a>>,
+}
+
+#[no_mangle]
+pub extern "C" fn rs_init<'a>() -> RustState<'a> {
+    // init_heap();
+    let peripherals = Peripherals::take();
+    let mut system = peripherals.DPORT.split();
+    let clocks = ClockControl::boot_defaults(system.clock_control).freeze();
+
+    // Disable the RTC and TIMG watchdog timers
+    let rtc = Rtc::new(peripherals.RTC_CNTL);
+    // let timer_group0 = TimerGroup::new(
+    //     peripherals.TIMG0,
+    //     &clocks,
+    //     &mut system.peripheral_clock_control,
+    // );
+    // let mut wdt0 = timer_group0.wdt;
+    // let timer_group1 = TimerGroup::new(
+    //     peripherals.TIMG1,
+    //     &clocks,
+    //     &mut system.peripheral_clock_control,
+    // );
+    // let mut wdt1 = timer_group1.wdt;
+    // rtc.rwdt.disable();
+    // wdt0.disable();
+    // wdt1.disable();
+    // setup logger
+    // To change the log_level change the env section in .config/cargo.toml
+    // or remove it and set ESP_LOGLEVEL manually before running cargo run
+    // this requires a clean rebuild because of https://github.com/rust-lang/cargo/issues/10358
+    esp_println::logger::init_logger_from_env();
+    // info!("Logger is setup");
+    // println!("Hello world!");
+
+    let io = hal::IO::new(peripherals.GPIO, peripherals.IO_MUX);
+    let i2c = I2C::new(
+        peripherals.I2C0,
+        io.pins.gpio21,
+        io.pins.gpio22,
+        1000u32.kHz(),
+        &mut system.peripheral_clock_control,
+        &clocks,
+    );
+    let display = OLEDDisplay::new(panel::new(i2c));
+
+    let encoder_dt = io.pins.gpio4.into_pull_up_input();
+    let encoder_clk = io.pins.gpio15.into_pull_up_input();
+    let encoder = RotaryEncoder::new(encoder_dt, encoder_clk).into_standard_mode();
+    critical_section::with(|cs| ENCODER.borrow_ref_mut(cs).replace(encoder));
+    let encoder_sw =
+        DebouncedSwitch::new(io.pins.gpio5.into_pull_up_input(), SwitchTimings::default());
+
+    let delay = hal::delay::Delay::new(&clocks);
+
+    let sensor_data = io.pins.gpio16.into_pull_up_input();
+    let sensor_clock = io.pins.gpio17.into_push_pull_output();
+    let h710 = h710::H710::new(sensor_data, sensor_clock, delay, h710::Mode::HZ40);
+
+    // let mut timer00 = timer_group0.timer0;
+    // hal::interrupt::enable(
+    //     hal::peripherals::Interrupt::TG0_T0_LEVEL,
+    //     hal::interrupt::Priority::Priority3,
+    // )
+    // .unwrap();
+    // // timer00.set_auto_reload(true);
+    // timer00.start(1100u32.micros());
+    // timer00.listen();
+    // critical_section::with(|cs| {
+    //     TIMER00.borrow_ref_mut(cs).replace(timer00);
+    // });
+
+    let mut state = State::new();
+    state.tease.seed(rtc.get_time_us() as u32);
+    storage::load_event_log(&mut state.log);
+    storage::load_settings(&mut state);
+    storage::load_limits(&mut state.limits);
+    /* A reboot does not end keyholder mode */
+    storage::load_lock(&mut state.lock, rtc.get_time_ms() as u32);
+    state.log_event(EventKind::Boot);
+    let menu = Menu::with_rows(display.layout().rows());
+
+    RustState {
+        controller: Box::new(Controller::new(state, menu, encoder_sw)),
+        display: Box::new(display),
+        telemetry: Box::new(Telemetry::new()),
+        console: Box::new(Console::new()),
+        h710: Box::new(h710),
+        rtc: Box::new(rtc),
+    }
+}
+
+/* Detents counted by the timer interrupt since the last call */
+fn take_encoder_steps() -> i32 {
+    critical_section::with(|cs| ENCODER_STEPS.borrow(cs).replace(0))
+}
+
+#[no_mangle]
+pub extern "C" fn loop_once(rust_state: *mut RustState) -> u8 {
+    let rust_state = unsafe { rust_state.as_mut().unwrap() };
+    let controller = &mut rust_state.controller;
+
+    /* Read user input, the menu turns it into commands */
+    controller.turn(take_encoder_steps(), rust_state.rtc.get_time_ms() as u32);
+    controller.poll_switch(rust_state.rtc.get_time_us());
+
+    /* Serial console commands */
+    let mut rx = [0u8; 32];
+    let len = serial::read(&mut rx);
+    rust_state
+        .console
+        .feed(&rx[..len], &mut controller.state, &mut esp_println::Printer)
+        .unwrap();
+
+    /* Check bluetooth */
+    controller.state.set_ble_connected(ble_is_connected());
+    controller.state.set_ble_name(ble_get_name());
+
+    controller.update(rust_state.rtc.get_time_ms() as u32);
+    let state = &mut controller.state;
+
+    /* Export the event log when a session ends */
+    if state.log.take_dump_request() {
+        state.log.dump(&mut esp_println::Printer).unwrap();
+        if state.log_to_flash && !storage::save_event_log(&state.log) {
+            info!("Failed to save event log");
+        }
+    }
+
+    /* Keep settings and limits across reboots, once they stopped changing */
+    if state.take_settings_save(state.cur_time_ms) {
+        if !storage::save_settings(state) {
+            info!("Failed to save settings");
+        }
+        if !storage::save_limits(&state.limits) {
+            info!("Failed to save limits");
+        }
+    }
+
+    /* The lock is saved right away, so switching off does not get around it */
+    let time = state.cur_time_ms;
+    if state.lock.take_save(time) && !storage::save_lock(&state.lock, time) {
+        info!("Failed to save lock");
+    }
+
+    /* Stream new events, or drop them while telemetry is off */
+    if state.telemetry_interval > 0 {
+        rust_state
+            .telemetry
+            .send_events(&state.log, &mut serial::write);
+    } else {
+        rust_state.telemetry.skip_events(&state.log);
+    }
+
+    /* Update display (only updates if necessary) */
+    rust_state
+        .display
+        .update(&controller.menu, &controller.state);
+
+    /* Read the sensor when a sample is ready */
+    if controller.wants_sensor() && rust_state.h710.is_ready() {
+        let val = rust_state.h710.read();
+        let detected = controller.sensor(val);
+        if let Some(val) = val.filter(|_| detected) {
+            send_samples(rust_state, val);
+        }
+    }
+    rust_state.controller.output()
+}
+
+fn send_samples(rust_state: &mut RustState, val: u32) {
+    let state = &rust_state.controller.state;
+    let time = state.cur_time_ms;
+    if !rust_state
+        .telemetry
+        .sample_due(time, state.telemetry_interval)
+    {
+        return;
+    }
+    let history = &rust_state.controller.history;
+    let records = [
+        Record::Sample {
+            time,
+            raw: val,
+            filtered: history.get_current_value(),
+            baseline: history.min_decay,
+            area: history.get_area(),
+            rhythm: history.get_rhythm(),
+        },
+        Record::Detector {
+            time,
+            running: state.running,
+            stimulating: state.stimulating,
+            cooldown: match state.hysteresis.is_active(time) {
+                true => state.hysteresis.remaining(time),
+                false => 0,
+            },
+            grace: state.grace_remaining(time).unwrap_or(0),
+            edges: state.stats.edges,
+        },
+        Record::Intensity {
+            time,
+            output: state.get_cur_intensity(),
+            base: state.base_intensity(),
+        },
+    ];
+    let mut frame = [0u8; MAX_FRAME];
+    for record in records.iter() {
+        let len = rust_state.telemetry.encode(record, &mut frame);
+        serial::write(&frame[..len]);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn rs_handle_timer(_: *mut cty::c_void) {
+    // info!("timer!");
+    critical_section::with(|cs| {
+        // let mut timer = TIMER00.borrow_ref_mut(cs);
+        // let timer = timer.as_mut().unwrap();
+
+        // if timer.is_interrupt_set() {
+        //     timer.clear_interrupt();
+        //     timer.start(1100u32.micros());
+
+        //     // esp_println::println!("Interrupt Level 2 - Timer0");
+        // }
+        let mut rotary_encoder = ENCODER.borrow_ref_mut(cs);
+        let rotary_encoder = rotary_encoder.as_mut().unwrap();
+        rotary_encoder.update();
+        let steps = ENCODER_STEPS.borrow(cs);
+        match rotary_encoder.direction() {
+            rotary_encoder_embedded::Direction::Clockwise => steps.set(steps.get().wrapping_add(1)),
+            rotary_encoder_embedded::Direction::Anticlockwise => {
+                steps.set(steps.get().wrapping_sub(1))
+            }
+            _ => {}
+        }
+    });
+}
@@ -1,16 +1,19 @@
+use core::cmp::Ordering;
 use core::fmt::Write;
-use display_interface::{DisplayError, WriteOnlyDataCommand};
 use embedded_graphics::{
     mono_font::{ascii::FONT_6X10, MonoTextStyle, MonoTextStyleBuilder},
     pixelcolor::BinaryColor,
     prelude::*,
-    primitives::{Circle, Line, PrimitiveStyle, Rectangle, Triangle},
+    primitives::{Arc, Circle, Line, PrimitiveStyle, Rectangle, Triangle},
     text::{Baseline, Text},
 };
 use heapless::String;
-use ssd1306::{mode::BufferedGraphicsMode, prelude::*, Ssd1306};
 
-use crate::{menu, state};
+use crate::lock::CODE_DIGITS;
+use crate::menu::{CodeEntry, Item, Level};
+use crate::param::{Param, ParamKind, Setting};
+use crate::program::{ProgramKind, ProgramState};
+use crate::{menu, profile::PROFILES, state};
 
 const THIN_STROKE: PrimitiveStyle<BinaryColor> = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
 const THICK_STROKE: PrimitiveStyle<BinaryColor> = PrimitiveStyle::with_stroke(BinaryColor::On, 2);
@@ -28,30 +31,114 @@ const UNDERLINED_TEXT_STYLE: MonoTextStyle<'_, BinaryColor> = MonoTextStyleBuild
 const FIRST_ROW: Point = Point::new(5, 0);
 const SECOND_ROW: Point = Point::new(5, 14);
 const INTER_FRAME_TIME_MS: u32 = 50;
+const ROW_HEIGHT: i32 = 11;
+const CHAR_WIDTH: i32 = 6;
+const BUTTON_SIZE: u32 = 20;
+const RING_SIZE: u32 = 26;
+const AREA_BAR_HEIGHT: u32 = 2;
 
-pub struct OLEDDisplay<DI> {
-    display: Ssd1306<DI, DisplaySize128x32, BufferedGraphicsMode<DisplaySize128x32>>,
-    next_update_ms: u32,
+/* Where things go on a panel of a given size, the screens are laid out for 128x32 and
+ * taller panels show more rows */
+#[derive(Copy, Clone)]
+pub struct Layout {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Layout {
+    pub fn new(size: Size) -> Layout {
+        Layout {
+            width: size.width as i32,
+            height: size.height as i32,
+        }
+    }
+
+    /* List rows that fit on the screen, the last one needs no gap below it */
+    pub fn rows(&self) -> usize {
+        ((self.height + 1) / ROW_HEIGHT).max(1) as usize
+    }
+
+    /* Characters of a list row between the cursor and the scrollbar */
+    fn row_chars(&self) -> usize {
+        ((self.width - CHAR_WIDTH - 3) / CHAR_WIDTH).max(0) as usize
+    }
+
+    /* Characters of a line from the left margin to the right edge */
+    fn line_chars(&self) -> usize {
+        ((self.width - FIRST_ROW.x) / CHAR_WIDTH).max(0) as usize
+    }
+
+    /* Characters of the main screen's lines that end before the cooldown ring */
+    fn main_chars(&self) -> usize {
+        let right = self.button().x - RING_SIZE as i32 / 2 - 2;
+        ((right - FIRST_ROW.x) / CHAR_WIDTH).max(0) as usize
+    }
+
+    fn row(&self, index: usize) -> i32 {
+        index as i32 * ROW_HEIGHT
+    }
+
+    /* The play/stop button in the top right corner */
+    fn button(&self) -> Point {
+        Point::new(self.width - 16, 16)
+    }
+
+    /* Along the bottom, up to the cooldown ring */
+    fn area_bar(&self) -> Rectangle {
+        let width = self.width - FIRST_ROW.x - RING_SIZE as i32 - 6;
+        Rectangle::new(
+            Point::new(FIRST_ROW.x, self.height - AREA_BAR_HEIGHT as i32),
+            Size::new(width.max(0) as u32, AREA_BAR_HEIGHT),
+        )
+    }
+
+    fn scrollbar_x(&self) -> i32 {
+        self.width - 1
+    }
+
+    /* Room for lines below the usual three rows of text */
+    fn extra_rows(&self) -> usize {
+        self.rows().saturating_sub(3)
+    }
+}
+
+/* Draws the screens into any monochrome target */
+pub struct Ui<'a, D> {
+    target: &'a mut D,
+    layout: Layout,
 }
 
-impl<DI> OLEDDisplay<DI>
+impl<'a, D> Ui<'a, D>
 where
-    DI: WriteOnlyDataCommand,
-    Ssd1306<DI, DisplaySize128x32, BufferedGraphicsMode<DisplaySize128x32>>:
-        DrawTarget<Color = BinaryColor, Error = DisplayError>,
+    D: DrawTarget<Color = BinaryColor>,
 {
-    pub fn new(interface: DI) -> OLEDDisplay<DI> {
-        let mut display = Ssd1306::new(interface, DisplaySize128x32, DisplayRotation::Rotate0)
-            .into_buffered_graphics_mode();
-        display.init().unwrap();
+    pub fn new(target: &'a mut D) -> Ui<'a, D> {
+        let layout = Layout::new(target.bounding_box().size);
+        Ui { target, layout }
+    }
 
-        OLEDDisplay {
-            display,
-            next_update_ms: 0,
+    pub fn draw(&mut self, menu: &menu::Menu, state: &state::State) -> Result<(), D::Error> {
+        use menu::MenuPosition::*;
+        match menu.position {
+            _ if state.emergency_stopped => self.print_emergency_stop(),
+            Main => self.print_main_menu(state),
+            List => match menu.level() {
+                Some(level) => self.print_list(state, level),
+                None => Ok(()),
+            },
+            /* Locked settings can be looked at, but not changed */
+            Param(setting) => self.print_param(state, setting.param(), !state.lock.is_locked()),
+            Summary(line) => self.print_summary(state, line),
+            Code(entry) => self.print_code_entry(state, entry),
         }
     }
 
-    fn print_text(&mut self, position: Point, text: &str, underlined: bool) {
+    fn print_text(
+        &mut self,
+        position: Point,
+        text: &str,
+        underlined: bool,
+    ) -> Result<(), D::Error> {
         Text::with_baseline(
             text,
             position,
@@ -61,165 +148,725 @@ where
             },
             Baseline::Top,
         )
-        .draw(&mut self.display)
-        .unwrap();
+        .draw(self.target)?;
+        Ok(())
     }
 
-    fn print_position(&mut self, pos: i32) {
-        let per_pos = 32 / 6;
-        Line::new(
-            Point::new(0, pos * per_pos),
-            Point::new(0, (pos + 1) * per_pos),
-        )
-        .into_styled(THICK_STROKE)
-        .draw(&mut self.display)
-        .unwrap()
+    /* Bar on the right edge showing which part of a list is on the screen */
+    fn print_scrollbar(
+        &mut self,
+        first: usize,
+        visible: usize,
+        total: usize,
+    ) -> Result<(), D::Error> {
+        if total <= visible {
+            return Ok(());
+        }
+        let height = self.layout.height as usize;
+        let start = (first * height / total) as i32;
+        let end = ((first + visible).min(total) * height / total) as i32;
+        let x = self.layout.scrollbar_x();
+        Line::new(Point::new(x, start), Point::new(x, end))
+            .into_styled(THICK_STROKE)
+            .draw(self.target)
     }
 
-    fn print_play_button(&mut self) {
-        Circle::with_center(Point::new(112, 16), 20)
+    fn write_row(text: &mut String<64>, state: &state::State, level: &Level, row: usize) {
+        match level.item(row) {
+            None => write!(text, "< {}", level.page.title),
+            Some(Item::Page(page)) => write!(text, "{} >", page.title),
+            Some(Item::Action(label, _)) => write!(text, "{}", label),
+            Some(Item::Summary) => write!(text, "Last session"),
+            Some(Item::Lock) => match state.lock.remaining(state.cur_time_ms) {
+                _ if !state.lock.is_locked() => write!(text, "Lock"),
+                Some(left) => write!(text, "Unlock ({}min)", left.div_ceil(60_000)),
+                None => write!(text, "Unlock"),
+            },
+            Some(Item::Param(setting)) => {
+                let param = setting.param();
+                let value = state.get_setting(*setting);
+                match param.kind {
+                    ParamKind::Value => write!(
+                        text,
+                        "{} {}{}",
+                        param.label,
+                        value / param.scale,
+                        param.unit
+                    ),
+                    ParamKind::Toggle => {
+                        write!(
+                            text,
+                            "{} {}",
+                            param.label,
+                            if value != 0 { "on" } else { "off" }
+                        )
+                    }
+                    ParamKind::Choice(option) => write!(text, "{} {}", param.label, option(value)),
+                }
+            }
+        }
+        .ok();
+    }
+
+    fn print_list(&mut self, state: &state::State, level: &Level) -> Result<(), D::Error> {
+        let rows = level.page.rows();
+        let visible = self.layout.rows();
+        for row in level.top..(level.top + visible).min(rows) {
+            let y = self.layout.row(row - level.top);
+            if row == level.cursor {
+                self.print_text(Point::new(0, y), ">", false)?;
+            }
+            /* Rows that do not fit are cut off, a full buffer just drops the rest */
+            let mut text = String::<64>::new();
+            Self::write_row(&mut text, state, level, row);
+            let text = clip(&text, self.layout.row_chars());
+            self.print_text(Point::new(CHAR_WIDTH, y), text, false)?;
+        }
+        self.print_scrollbar(level.top, visible, rows)
+    }
+
+    fn print_play_button(&mut self) -> Result<(), D::Error> {
+        let center = self.layout.button();
+        Circle::with_center(center, BUTTON_SIZE)
             .into_styled(THIN_STROKE)
-            .draw(&mut self.display)
-            .unwrap();
+            .draw(self.target)?;
 
         Triangle::new(
-            Point::new(110, 12),
-            Point::new(110, 20),
-            Point::new(117, 16),
+            center + Point::new(-2, -4),
+            center + Point::new(-2, 4),
+            center + Point::new(5, 0),
         )
         .into_styled(FILLED_STYLE)
-        .draw(&mut self.display)
-        .unwrap();
+        .draw(self.target)
     }
 
-    fn print_stop_button(&mut self) {
-        Circle::with_center(Point::new(112, 16), 20)
+    fn print_stop_button(&mut self) -> Result<(), D::Error> {
+        let center = self.layout.button();
+        Circle::with_center(center, BUTTON_SIZE)
             .into_styled(THIN_STROKE)
-            .draw(&mut self.display)
-            .unwrap();
-        Rectangle::with_center(Point::new(112, 16), Size::new(8, 8))
+            .draw(self.target)?;
+        Rectangle::with_center(center, Size::new(8, 8))
             .into_styled(FILLED_STYLE)
-            .draw(&mut self.display)
-            .unwrap();
+            .draw(self.target)
+    }
+
+    /* Empties clockwise as the cooldown runs out */
+    fn print_cooldown_ring(&mut self, remaining: u32, duration: u32) -> Result<(), D::Error> {
+        let sweep = remaining.min(duration) as f32 / duration.max(1) as f32 * 360.0;
+        Arc::with_center(
+            self.layout.button(),
+            RING_SIZE,
+            Angle::from_degrees(-90.0),
+            Angle::from_degrees(sweep),
+        )
+        .into_styled(THICK_STROKE)
+        .draw(self.target)
+    }
+
+    /* How close the current peak is to counting as an edge, the tick marks the threshold */
+    fn print_area_bar(&mut self, area: u32, threshold: u32) -> Result<(), D::Error> {
+        let track = self.layout.area_bar();
+        let full = track.size.width as u64;
+        let width = (area as u64 * full / threshold.max(1) as u64).min(full) as u32;
+        Rectangle::new(track.top_left, Size::new(width, track.size.height))
+            .into_styled(FILLED_STYLE)
+            .draw(self.target)?;
+        let end = track.top_left + Point::new(full as i32, -1);
+        Line::new(end, end + Point::new(0, track.size.height as i32))
+            .into_styled(THIN_STROKE)
+            .draw(self.target)
     }
 
-    fn print_main_menu(&mut self, state: &state::State) {
+    fn print_main_menu(&mut self, state: &state::State) -> Result<(), D::Error> {
         if state.running {
-            self.print_stop_button();
+            self.print_stop_button()?;
+            if state.hysteresis.is_active(state.cur_time_ms) {
+                self.print_cooldown_ring(
+                    state.hysteresis.remaining(state.cur_time_ms),
+                    state.hysteresis.duration(),
+                )?;
+            }
+            self.print_area_bar(state.area, state.peak_area_threshold)?;
         } else {
-            self.print_play_button();
+            self.print_play_button()?;
         };
-        let mut text: String<30> = String::<30>::new();
+        /* Sized for the longest numbers the settings and timers can reach */
+        let mut text = String::<48>::new();
+        let mut next_cooldown = String::<24>::new();
+        if state.tease_mode {
+            write!(
+                &mut next_cooldown,
+                "CD {}-{}s",
+                state.tease_cooldown_min / 1_000,
+                state.tease_cooldown_max / 1_000
+            )
+            .unwrap();
+        } else {
+            write!(
+                &mut next_cooldown,
+                "CD {}s",
+                state.next_cooldown(state.cur_time_ms) / 1_000
+            )
+            .unwrap();
+        }
         let stim_str = if !state.running {
-            "Ready\n"
-        } else if state.stimulating {
             write!(
                 &mut text,
-                "Stimulation\n{:.1}s",
-                (state.cur_time_ms - state.stim_start_time) as f32 / 1000f32
+                "{}\n{}",
+                if state.auto_start {
+                    "Ready (auto)"
+                } else {
+                    "Ready"
+                },
+                next_cooldown
             )
             .unwrap();
             text.as_str()
+        } else if state.stimulating {
+            let elapsed = state.cur_time_ms.wrapping_sub(state.stim_start_time) as f32 / 1000f32;
+            if let Some(progress) = state.ramp_progress() {
+                write!(&mut text, "Ramp {}%", progress / 10).unwrap();
+            } else {
+                write!(&mut text, "Stimulation").unwrap();
+            }
+            if let Some(grace) = state.grace_remaining(state.cur_time_ms) {
+                write!(
+                    &mut text,
+                    "\n{:.1}s G {:.1}s",
+                    elapsed,
+                    grace as f32 / 1000f32
+                )
+                .unwrap();
+            } else {
+                write!(&mut text, "\n{:.1}s {}", elapsed, next_cooldown).unwrap();
+            }
+            text.as_str()
+        } else if state.paused {
+            write!(&mut text, "Paused\nSensor removed").unwrap();
+            text.as_str()
         } else {
             write!(
                 &mut text,
                 "No Stimulation\n{:.1}s",
-                state
-                    .hysteresis
-                    .remaining(state.cur_time_ms, state.cooldown_time) as f32
-                    / 1000f32
+                state.hysteresis.remaining(state.cur_time_ms) as f32 / 1000f32
             )
             .unwrap();
             text.as_str()
         };
 
         let mut text = String::<100>::new();
-        write!(&mut text, "{}\nB: {}", stim_str, state.ble_name).unwrap();
-        self.print_text(FIRST_ROW, text.as_str(), false);
+        write!(&mut text, "{}\n{}/20 ", stim_str, state.base_intensity()).unwrap();
+        /* An edge goal program counts the edges itself */
+        let goal_shown = state.program_kind == ProgramKind::EdgeGoal
+            && state.program.state == ProgramState::Edging;
+        if state.running && !goal_shown {
+            write!(&mut text, "E{} ", state.stats.edges).unwrap();
+        }
+        Self::write_program_progress(&mut text, state);
+        /* Lines that would run into the button are cut off */
+        let chars = self.layout.main_chars();
+        let mut clipped = String::<100>::new();
+        for (i, line) in text.lines().enumerate() {
+            if i > 0 {
+                writeln!(&mut clipped).unwrap();
+            }
+            clipped.push_str(clip(line, chars)).unwrap();
+        }
+        self.print_text(FIRST_ROW, clipped.as_str(), false)?;
+
+        /* Taller panels also show how the session is going */
+        if self.layout.extra_rows() > 0 && state.running {
+            let length = state.cur_time_ms.wrapping_sub(state.stats.start_time) / 1_000;
+            let mut text = String::<30>::new();
+            write!(&mut text, "Session {}:{:02}", length / 60, length % 60).unwrap();
+            let y = self.layout.row(3);
+            self.print_text(Point::new(FIRST_ROW.x, y), text.as_str(), false)?;
+        }
+        Ok(())
     }
 
-    fn print_value_menu(&mut self, name: &str, value: u32, unit: &str, underlined: bool) {
-        self.print_text(FIRST_ROW, name, false);
+    fn write_program_progress<const N: usize>(text: &mut String<N>, state: &state::State) {
+        let remaining = state
+            .program
+            .remaining(state.cur_time_ms, state.program_duration)
+            / 1_000;
+        match (state.program_kind, state.program.state) {
+            (_, ProgramState::Finish) => write!(text, "Finish!"),
+            (_, ProgramState::Ruin) => write!(text, "Ruin"),
+            (_, ProgramState::Done) => write!(text, "Done"),
+            (ProgramKind::EdgeGoal, ProgramState::Edging) => write!(
+                text,
+                "E {}/{}",
+                state.program.edges, state.program_edge_goal
+            ),
+            (ProgramKind::Timed, ProgramState::Edging) => {
+                write!(text, "T {}:{:02}", remaining / 60, remaining % 60)
+            }
+            (ProgramKind::DenyThenRuin, ProgramState::Edging) => {
+                write!(text, "D {}:{:02}", remaining / 60, remaining % 60)
+            }
+            /* During a session the edge count takes the name's place */
+            _ if state.running => Ok(()),
+            _ => write!(text, "B: {}", state.ble_name),
+        }
+        /* A name too long for the buffer would not fit on the screen either */
+        .ok();
+    }
+
+    fn print_param(
+        &mut self,
+        state: &state::State,
+        param: &Param,
+        editing: bool,
+    ) -> Result<(), D::Error> {
+        let value = state.get_setting(param.setting);
+        match (param.setting, param.kind) {
+            (Setting::CooldownPolicy, _) => self.print_cooldown_policy_menu(state),
+            (Setting::Profile, _) => self.print_profile_menu(state),
+            (Setting::Program, _) => self.print_program_menu(state),
+            (Setting::Intensity, _) => self.print_ble_menu(state, editing),
+            (_, ParamKind::Value) => {
+                self.print_value_menu(param.label, value / param.scale, param.unit, editing)
+            }
+            (_, ParamKind::Toggle) => self.print_toggle_menu(param.label, value != 0),
+            (_, ParamKind::Choice(option)) => self.print_choice_menu(param.label, option(value)),
+        }
+    }
+
+    fn print_value_menu(
+        &mut self,
+        name: &str,
+        value: u32,
+        unit: &str,
+        underlined: bool,
+    ) -> Result<(), D::Error> {
+        self.print_text(FIRST_ROW, name, false)?;
 
         let mut text = String::<30>::new();
         write!(&mut text, "{}{}", value, unit).unwrap();
-        self.print_text(SECOND_ROW, text.as_str(), underlined);
+        self.print_text(SECOND_ROW, text.as_str(), underlined)
+    }
+
+    fn print_cooldown_policy_menu(&mut self, state: &state::State) -> Result<(), D::Error> {
+        use crate::cooldown::CooldownPolicy::*;
+        self.print_text(FIRST_ROW, "Cooldown mode", false)?;
+        let mut text = String::<30>::new();
+        match state.cooldown_policy {
+            Fixed => write!(&mut text, "{}", state.cooldown_policy.name()),
+            Linear => write!(
+                &mut text,
+                "{} +{}s",
+                state.cooldown_policy.name(),
+                state.cooldown_increment / 1_000
+            ),
+            Exponential => write!(
+                &mut text,
+                "{} x{}.{:02}",
+                state.cooldown_policy.name(),
+                state.cooldown_factor / 100,
+                state.cooldown_factor % 100
+            ),
+        }
+        .unwrap();
+        self.print_text(SECOND_ROW, text.as_str(), false)
+    }
+
+    fn print_choice_menu(&mut self, name: &str, value: &str) -> Result<(), D::Error> {
+        self.print_text(FIRST_ROW, name, false)?;
+        self.print_text(SECOND_ROW, value, false)
+    }
+
+    fn print_profile_menu(&mut self, state: &state::State) -> Result<(), D::Error> {
+        let mut text = String::<30>::new();
+        write!(&mut text, "Profile: {}", PROFILES[state.profile].name).unwrap();
+        self.print_text(FIRST_ROW, text.as_str(), false)?;
+        let mut text = String::<30>::new();
+        write!(
+            &mut text,
+            "{} {}s {}%",
+            state.pattern.name(),
+            state.pattern_period as f32 / 1000f32,
+            state.pattern_depth
+        )
+        .unwrap();
+        self.print_text(SECOND_ROW, text.as_str(), false)
     }
 
-    fn print_ble_menu(&mut self, state: &state::State, underlined: bool) {
+    fn print_program_menu(&mut self, state: &state::State) -> Result<(), D::Error> {
+        use crate::program::ProgramKind::*;
+        self.print_text(FIRST_ROW, "Program", false)?;
+        let mut text = String::<30>::new();
+        match state.program_kind {
+            Free => write!(&mut text, "{}", state.program_kind.name()),
+            EdgeGoal => write!(
+                &mut text,
+                "{} {}",
+                state.program_kind.name(),
+                state.program_edge_goal
+            ),
+            Timed | DenyThenRuin => write!(
+                &mut text,
+                "{} {}min",
+                state.program_kind.name(),
+                state.program_duration / 60_000
+            ),
+        }
+        .unwrap();
+        self.print_text(SECOND_ROW, text.as_str(), false)
+    }
+
+    fn print_summary(&mut self, state: &state::State, first_line: usize) -> Result<(), D::Error> {
+        let lines = state.stats.summary_lines();
+        /* The title takes the first row */
+        let visible = self.layout.rows() - 1;
+        let mut text = String::<200>::new();
+        write!(&mut text, "Summary").unwrap();
+        for line in first_line..(first_line + visible).min(lines) {
+            writeln!(&mut text).unwrap();
+            state.stats.write_summary_line(line, &mut text).unwrap();
+        }
+        self.print_text(FIRST_ROW, text.as_str(), false)?;
+        self.print_scrollbar(first_line, visible, lines)
+    }
+
+    fn print_emergency_stop(&mut self) -> Result<(), D::Error> {
+        self.print_text(FIRST_ROW, "EMERGENCY STOP", true)?;
+        self.print_text(SECOND_ROW, "Click to confirm", false)
+    }
+
+    fn print_code_entry(&mut self, state: &state::State, entry: CodeEntry) -> Result<(), D::Error> {
+        let title = match state.lock.is_locked() {
+            true => "Unlock code",
+            false => "Lock code",
+        };
+        self.print_text(FIRST_ROW, title, false)?;
+        let mut text = String::<16>::new();
+        for position in 0..CODE_DIGITS {
+            let c = match position.cmp(&entry.entered) {
+                Ordering::Less => '*',
+                Ordering::Equal => char::from_digit(entry.digit, 10).unwrap_or('?'),
+                Ordering::Greater => '_',
+            };
+            write!(&mut text, "{} ", c).unwrap();
+        }
+        self.print_text(SECOND_ROW, text.as_str(), false)
+    }
+
+    fn print_toggle_menu(&mut self, name: &str, value: bool) -> Result<(), D::Error> {
+        self.print_text(FIRST_ROW, name, false)?;
+        self.print_text(SECOND_ROW, if value { "on" } else { "off" }, false)
+    }
+
+    fn print_ble_menu(&mut self, state: &state::State, underlined: bool) -> Result<(), D::Error> {
         if !state.ble_connected {
-            self.print_text(FIRST_ROW, "BLE: not connected", false);
-            self.print_text(SECOND_ROW, "n/a", false);
+            self.print_text(FIRST_ROW, "BLE: not connected", false)?;
+            self.print_text(SECOND_ROW, "n/a", false)
         } else {
+            /* Toys may have names longer than the screen is wide */
+            let mut text = String::<48>::new();
+            write!(&mut text, "BLE: {}", state.ble_name).ok();
+            self.print_text(FIRST_ROW, clip(&text, self.layout.line_chars()), false)?;
             let mut text = String::<30>::new();
-            write!(&mut text, "BLE: {}", state.ble_name).unwrap();
-            self.print_text(FIRST_ROW, text.as_str(), false);
-            let mut text = String::<30>::new();
-            if state.running {
+            if state.running && state.tease_mode {
+                write!(&mut text, "{}/20 (tease)", state.tease.intensity).unwrap();
+            } else if state.running && state.auto_ramp {
+                write!(&mut text, "{}/20 (ramp)", state.auto_ramp_level).unwrap();
+            } else if state.running {
                 write!(&mut text, "{}/20 (auto)", state.intensity).unwrap();
-                self.print_text(SECOND_ROW, text.as_str(), underlined);
             } else {
                 write!(&mut text, "{}/20 (manual)", state.intensity).unwrap();
-                self.print_text(SECOND_ROW, text.as_str(), underlined);
             }
+            self.print_text(SECOND_ROW, text.as_str(), underlined)
+        }
+    }
+}
+
+/* The start of a line that fits in `chars` characters */
+fn clip(line: &str, chars: usize) -> &str {
+    let end = line
+        .char_indices()
+        .nth(chars)
+        .map_or(line.len(), |(end, _)| end);
+    &line[..end]
+}
+
+/* A display with a frame buffer that is drawn into and then sent out in one go */
+pub trait Panel: DrawTarget<Color = BinaryColor> {
+    fn clear_buffer(&mut self);
+    fn flush(&mut self);
+}
+
+pub struct OLEDDisplay<P> {
+    panel: P,
+    next_update_ms: u32,
+    events_seen: u32,
+}
+
+impl<P> OLEDDisplay<P>
+where
+    P: Panel,
+{
+    pub fn new(panel: P) -> OLEDDisplay<P> {
+        OLEDDisplay {
+            panel,
+            next_update_ms: 0,
+            events_seen: 0,
         }
     }
 
+    pub fn layout(&self) -> Layout {
+        Layout::new(self.panel.bounding_box().size)
+    }
+
     pub fn update(&mut self, menu: &menu::Menu, state: &state::State) {
-        if self.next_update_ms > state.cur_time_ms {
+        /* Redraw right away when something was changed, otherwise at the frame rate */
+        let changed = state.log.recorded() != self.events_seen;
+        if self.next_update_ms > state.cur_time_ms && !changed {
             return;
         }
 
         self.next_update_ms = state.cur_time_ms + INTER_FRAME_TIME_MS;
+        self.events_seen = state.log.recorded();
 
-        use menu::MenuPosition::*;
-        self.display.clear_buffer();
-        match menu.position {
-            Main => {
-                self.print_main_menu(state);
-                self.print_position(0)
-            }
-            Peak => {
-                self.print_value_menu("Sensitivity", state.peak_value_thresh, "", false);
-                self.print_position(1);
-            }
-            PeakSelect => {
-                self.print_value_menu("Sensitivity", state.peak_value_thresh, "", true);
-                self.print_position(1);
-            }
-            Area => {
-                self.print_value_menu("Density", state.peak_area_threshold, "", false);
-                self.print_position(2);
-            }
-            AreaSelect => {
-                self.print_value_menu("Density", state.peak_area_threshold, "", true);
-                self.print_position(2);
-            }
-            Duration => {
-                self.print_value_menu("Duration", state.peak_release_time_thresh, "ms", false);
-                self.print_position(3);
-            }
-            DurationSelect => {
-                self.print_value_menu("Duration", state.peak_release_time_thresh, "ms", true);
-                self.print_position(3);
-            }
-            Cooldown => {
-                self.print_value_menu("Cooldown time", state.cooldown_time / 1_000, "s", false);
-                self.print_position(4);
-            }
-            CooldownSelect => {
-                self.print_value_menu("Cooldown time", state.cooldown_time / 1_000, "s", true);
-                self.print_position(4);
-            }
-            Intensity => {
-                self.print_ble_menu(state, false);
-                self.print_position(5);
-            }
-            IntensitySelect => {
-                self.print_ble_menu(state, true);
-                self.print_position(5);
+        self.panel.clear_buffer();
+        /* Drawing only touches the frame buffer, a failed frame is replaced by the next one */
+        Ui::new(&mut self.panel).draw(menu, state).ok();
+        self.panel.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{dispatch, Command};
+    use crate::framebuffer::FrameBuffer;
+    use crate::menu::{Menu, MenuPosition};
+    use embedded_graphics::mock_display::MockDisplay;
+
+    #[test]
+    fn layout_fits_panel() {
+        let small = Layout::new(Size::new(128, 32));
+        assert_eq!(small.rows(), 3);
+        assert_eq!(small.row_chars(), 19);
+        assert_eq!(small.button(), Point::new(112, 16));
+        assert_eq!(Layout::new(Size::new(128, 64)).rows(), 5);
+    }
+
+    #[test]
+    fn draws_on_mock_display() {
+        let state = state::State::new();
+        let mut menu = menu::Menu::with_rows(Layout::new(Size::new(64, 64)).rows());
+        menu.turn(&state, 1);
+
+        let mut display = MockDisplay::<BinaryColor>::new();
+        display.set_allow_out_of_bounds_drawing(true);
+        display.set_allow_overdraw(true);
+        Ui::new(&mut display).draw(&menu, &state).unwrap();
+
+        /* All five rows of the list are used, with the cursor in front of them */
+        let area = display.affected_area();
+        assert!(area.top_left.x < CHAR_WIDTH);
+        assert!(area.bottom_right().unwrap().y >= 4 * ROW_HEIGHT);
+    }
+
+    /* Draws a screen on a panel of the given size and compares it to src/snapshots/<name>.txt.
+     * Run with UPDATE_SNAPSHOTS=1 to write the snapshots after changing a screen on purpose */
+    fn check<const W: usize, const H: usize>(name: &str, menu: &Menu, state: &state::State) {
+        let mut frame = FrameBuffer::<W, H>::new();
+        Ui::new(&mut frame).draw(menu, state).unwrap();
+        let frame = frame.to_string();
+
+        let path = format!("{}/src/snapshots/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &frame).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            expected == frame,
+            "{} does not match {}, drawn:\n{}",
+            name,
+            path,
+            frame
+        );
+    }
+
+    fn screen(name: &str, menu: &Menu, state: &state::State) {
+        check::<128, 32>(name, menu, state);
+    }
+
+    fn at(position: MenuPosition) -> Menu {
+        let mut menu = Menu::default();
+        menu.position = position;
+        menu
+    }
+
+    fn running() -> state::State {
+        let mut state = state::State::new();
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        state.cur_time_ms = 13_300;
+        state
+    }
+
+    #[test]
+    fn main_screens() {
+        let mut state = state::State::new();
+        screen("main_ready", &Menu::default(), &state);
+
+        state = running();
+        screen("main_stimulation", &Menu::default(), &state);
+        check::<128, 64>("main_stimulation_128x64", &Menu::default(), &state);
+
+        state.area = state.peak_area_threshold / 2;
+        screen("main_peak", &Menu::default(), &state);
+
+        dispatch(&mut state, Command::Pause);
+        screen("main_paused", &Menu::default(), &state);
+        dispatch(&mut state, Command::Start);
+
+        let mut state = edged(state);
+        state.area = 0;
+        state.cur_time_ms += 2_000;
+        screen("main_cooldown", &Menu::default(), &state);
+
+        /* The program's count takes the place of the plain one */
+        let mut state = state::State::new();
+        state.set_setting(Setting::Program, ProgramKind::EdgeGoal as u32);
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        let state = edged(state);
+        assert_eq!(state.program.edges, 1);
+        screen("main_edge_goal", &Menu::default(), &state);
+    }
+
+    /* Feeds the detector rising pressure until it calls an edge, as the main loop would */
+    fn edged(state: state::State) -> state::State {
+        let mut controller = crate::controller::tests::controller();
+        controller.state = state;
+        let start = controller.state.cur_time_ms;
+        controller.sensor(Some(1_000_000));
+        let high = 1_000_000 + 4 * controller.state.peak_value_thresh;
+        for time in (start..start + 60_000).step_by(25) {
+            controller.update(time);
+            controller.sensor(Some(high));
+            if controller.state.hysteresis.is_active(time) {
+                return controller.state;
             }
         }
-        self.display.flush().unwrap();
+        panic!("no edge after a minute of pressure");
+    }
+
+    #[test]
+    fn long_numbers_fit_main_screen() {
+        let mut state = running();
+        state.tease_mode = true;
+        state.tease_cooldown_min = u32::MAX;
+        state.tease_cooldown_max = u32::MAX;
+        state.stim_start_time = state.cur_time_ms.wrapping_add(1);
+        screen("main_long_numbers", &Menu::default(), &state);
+        text_clears_button::<128, 32>(&state);
+        text_clears_button::<128, 64>(&state);
+    }
+
+    /* Nothing but the button and its ring is drawn where the full ring would be */
+    fn text_clears_button<const W: usize, const H: usize>(state: &state::State) {
+        let mut screen = FrameBuffer::<W, H>::new();
+        Ui::new(&mut screen).draw(&Menu::default(), state).unwrap();
+        let mut button = FrameBuffer::<W, H>::new();
+        let mut ui = Ui::new(&mut button);
+        ui.print_stop_button().unwrap();
+        ui.print_cooldown_ring(1, 1).unwrap();
+
+        let ring = Rectangle::with_center(ui.layout.button(), Size::new_equal(RING_SIZE + 2));
+        for point in ring.points() {
+            let (x, y) = (point.x as usize, point.y as usize);
+            assert!(
+                !screen.pixel(x, y) || button.pixel(x, y),
+                "text at {:?} runs into the button on {}x{}",
+                point,
+                W,
+                H
+            );
+        }
+    }
+
+    #[test]
+    fn list_screens() {
+        let state = state::State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        screen("list_home", &menu, &state);
+        menu.turn(&state, 5);
+        screen("list_scrolled", &menu, &state);
+        check::<128, 64>("list_scrolled_128x64", &menu, &state);
+    }
+
+    #[test]
+    fn param_screens() {
+        let mut state = state::State::new();
+        for (name, setting) in [
+            ("param_value", Setting::Cooldown),
+            ("param_toggle", Setting::AutoStart),
+            ("param_choice", Setting::RampCurve),
+            ("param_cooldown_policy", Setting::CooldownPolicy),
+            ("param_profile", Setting::Profile),
+            ("param_program", Setting::Program),
+            ("param_intensity", Setting::Intensity),
+        ] {
+            screen(name, &at(MenuPosition::Param(setting)), &state);
+        }
+
+        state.set_ble_connected(true);
+        state.set_ble_name("Lush");
+        screen(
+            "param_intensity_connected",
+            &at(MenuPosition::Param(Setting::Intensity)),
+            &state,
+        );
+        /* The longest name the toy side allows */
+        state.set_ble_name("LVS-Lush3-0123456789abcdefABCDE");
+        screen(
+            "param_intensity_long_name",
+            &at(MenuPosition::Param(Setting::Intensity)),
+            &state,
+        );
+
+        state.lock(1234);
+        screen(
+            "param_locked",
+            &at(MenuPosition::Param(Setting::Cooldown)),
+            &state,
+        );
+    }
+
+    #[test]
+    fn summary_screen() {
+        let mut state = edged(running());
+        state.cur_time_ms += 60_000;
+        state.toggle();
+        screen("summary", &at(MenuPosition::Summary(0)), &state);
+        screen("summary_scrolled", &at(MenuPosition::Summary(4)), &state);
+    }
+
+    #[test]
+    fn code_entry_screens() {
+        let mut state = state::State::new();
+        let entry = CodeEntry {
+            code: 12,
+            entered: 2,
+            digit: 7,
+        };
+        screen("code_lock", &at(MenuPosition::Code(entry)), &state);
+        state.lock(1234);
+        screen("code_unlock", &at(MenuPosition::Code(entry)), &state);
+    }
+
+    #[test]
+    fn emergency_stop_covers_everything() {
+        let mut state = running();
+        state.emergency_stop();
+        screen("emergency_stop", &Menu::default(), &state);
+        screen("emergency_stop", &at(MenuPosition::Summary(0)), &state);
     }
 }
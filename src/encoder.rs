@@ -0,0 +1,116 @@
+/* Detents per second a turn may reach before steps start growing */
+const ACCEL_START: u32 = 8;
+/* Extra detents per second for each additional step per detent */
+const ACCEL_RAMP: u32 = 3;
+const ACCEL_MAX: u32 = 40;
+/* A pause this long ends a spin */
+const SPIN_TIMEOUT_MS: u32 = 250;
+
+/* Turns the detents counted by the timer interrupt into menu steps */
+pub struct Encoder {
+    last_time: u32,
+    /* Smoothed detents per second of the current spin, signed by direction */
+    speed: i32,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder {
+            last_time: 0,
+            speed: 0,
+        }
+    }
+
+    /* Fast spins take larger steps, so long ranges can be dialed quickly */
+    pub fn steps(&mut self, detents: i32, time: u32, inverted: bool, accelerate: bool) -> i32 {
+        if detents == 0 {
+            return 0;
+        }
+        let detents = match inverted {
+            true => -detents,
+            false => detents,
+        };
+
+        let gap = time.wrapping_sub(self.last_time).max(1);
+        self.last_time = time;
+        let speed =
+            (detents.unsigned_abs().saturating_mul(1_000) / gap).min(i32::MAX as u32) as i32;
+        let speed = detents.signum() * speed;
+        /* A pause or a change of direction starts over at single steps */
+        let reversed = self.speed != 0 && speed.signum() != self.speed.signum();
+        self.speed = match gap > SPIN_TIMEOUT_MS || reversed {
+            true => 0,
+            false => (self.speed + speed) / 2,
+        };
+
+        if !accelerate {
+            return detents;
+        }
+        let factor = match self.speed.unsigned_abs() {
+            speed if speed <= ACCEL_START => 1,
+            speed => (1 + (speed - ACCEL_START) / ACCEL_RAMP).min(ACCEL_MAX),
+        };
+        detents.saturating_mul(factor as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Turns `detents` single detents, `gap` ms apart, and sums the steps */
+    fn spin(
+        encoder: &mut Encoder,
+        time: &mut u32,
+        detents: i32,
+        gap: u32,
+        accelerate: bool,
+    ) -> i32 {
+        let mut total = 0;
+        for _ in 0..detents.unsigned_abs() {
+            *time += gap;
+            total += encoder.steps(detents.signum(), *time, false, accelerate);
+        }
+        total
+    }
+
+    #[test]
+    fn slow_turns_take_single_steps() {
+        let mut encoder = Encoder::new();
+        let mut time = 1_000;
+        assert_eq!(spin(&mut encoder, &mut time, 20, 500, true), 20);
+        assert_eq!(spin(&mut encoder, &mut time, -5, 500, true), -5);
+    }
+
+    #[test]
+    fn fast_spins_take_larger_steps() {
+        let mut encoder = Encoder::new();
+        let mut time = 1_000;
+        let fast = spin(&mut encoder, &mut time, 40, 20, true);
+        assert!(fast > 200, "40 fast detents took {} steps", fast);
+        assert!(fast <= 40 * ACCEL_MAX as i32);
+
+        /* A pause starts over at single steps */
+        time += SPIN_TIMEOUT_MS + 1;
+        assert_eq!(encoder.steps(1, time, false, true), 1);
+        /* So does a change of direction */
+        spin(&mut encoder, &mut time, 20, 20, true);
+        time += 20;
+        assert_eq!(encoder.steps(-1, time, false, true), -1);
+    }
+
+    #[test]
+    fn acceleration_can_be_turned_off() {
+        let mut encoder = Encoder::new();
+        let mut time = 1_000;
+        assert_eq!(spin(&mut encoder, &mut time, 40, 20, false), 40);
+    }
+
+    #[test]
+    fn inverted_direction() {
+        let mut encoder = Encoder::new();
+        assert_eq!(encoder.steps(2, 1_000, true, true), -2);
+        assert_eq!(encoder.steps(-3, 2_000, true, true), 3);
+        assert_eq!(encoder.steps(0, 3_000, true, true), 0);
+    }
+}
@@ -0,0 +1,335 @@
+use core::fmt::{Result, Write};
+use heapless::HistoryBuffer;
+
+use crate::param::Setting;
+
+const LOG_SIZE: usize = 64;
+pub const RECORD_SIZE: usize = 13;
+pub const SERIALIZED_SIZE: usize = LOG_SIZE * RECORD_SIZE;
+
+#[derive(Copy, Clone)]
+pub enum EventKind {
+    Boot,
+    SessionStart,
+    SessionStop,
+    Edge { area: u32, peak: u32 },
+    CooldownStart { duration: u32 },
+    CooldownEnd,
+    Orgasm,
+    ToyConnected,
+    ToyDisconnected,
+    SensorFault,
+    SettingChanged { setting: Setting, value: u32 },
+    Locked { duration: u32 },
+    Unlocked,
+    EmergencyStop,
+    EmergencyStopAcknowledged,
+    LimitReached { setting: Setting },
+    SensorInserted,
+    SensorRemoved,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        use EventKind::*;
+        match self {
+            Boot => "boot",
+            SessionStart => "session_start",
+            SessionStop => "session_stop",
+            Edge { .. } => "edge",
+            CooldownStart { .. } => "cooldown_start",
+            CooldownEnd => "cooldown_end",
+            Orgasm => "orgasm",
+            ToyConnected => "toy_connected",
+            ToyDisconnected => "toy_disconnected",
+            SensorFault => "sensor_fault",
+            SettingChanged { .. } => "setting_changed",
+            Locked { .. } => "locked",
+            Unlocked => "unlocked",
+            EmergencyStop => "emergency_stop",
+            EmergencyStopAcknowledged => "emergency_stop_ack",
+            LimitReached { .. } => "limit_reached",
+            SensorInserted => "sensor_inserted",
+            SensorRemoved => "sensor_removed",
+        }
+    }
+
+    pub fn encode(&self) -> (u8, u32, u32) {
+        use EventKind::*;
+        match *self {
+            Boot => (0, 0, 0),
+            SessionStart => (1, 0, 0),
+            SessionStop => (2, 0, 0),
+            Edge { area, peak } => (3, area, peak),
+            CooldownStart { duration } => (4, duration, 0),
+            CooldownEnd => (5, 0, 0),
+            Orgasm => (6, 0, 0),
+            ToyConnected => (7, 0, 0),
+            ToyDisconnected => (8, 0, 0),
+            SensorFault => (9, 0, 0),
+            SettingChanged { setting, value } => (10, setting as u32, value),
+            Locked { duration } => (11, duration, 0),
+            Unlocked => (12, 0, 0),
+            EmergencyStop => (13, 0, 0),
+            EmergencyStopAcknowledged => (14, 0, 0),
+            LimitReached { setting } => (15, setting as u32, 0),
+            SensorInserted => (16, 0, 0),
+            SensorRemoved => (17, 0, 0),
+        }
+    }
+
+    pub fn decode(code: u8, a: u32, b: u32) -> Option<EventKind> {
+        use EventKind::*;
+        Some(match code {
+            0 => Boot,
+            1 => SessionStart,
+            2 => SessionStop,
+            3 => Edge { area: a, peak: b },
+            4 => CooldownStart { duration: a },
+            5 => CooldownEnd,
+            6 => Orgasm,
+            7 => ToyConnected,
+            8 => ToyDisconnected,
+            9 => SensorFault,
+            10 => SettingChanged {
+                setting: Setting::from_index(a)?,
+                value: b,
+            },
+            11 => Locked { duration: a },
+            12 => Unlocked,
+            13 => EmergencyStop,
+            14 => EmergencyStopAcknowledged,
+            15 => LimitReached {
+                setting: Setting::from_index(a)?,
+            },
+            16 => SensorInserted,
+            17 => SensorRemoved,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Event {
+    pub time: u32,
+    pub kind: EventKind,
+}
+
+pub struct EventLog {
+    events: HistoryBuffer<Event, LOG_SIZE>,
+    recorded: u32,
+    dump_requested: bool,
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog {
+            events: HistoryBuffer::new(),
+            recorded: 0,
+            dump_requested: false,
+        }
+    }
+
+    pub fn record(&mut self, time: u32, kind: EventKind) {
+        self.events.write(Event { time, kind });
+        self.recorded = self.recorded.wrapping_add(1);
+    }
+
+    /* Number of events recorded since boot, including the ones pushed out of the buffer */
+    pub fn recorded(&self) -> u32 {
+        self.recorded
+    }
+
+    /* Events recorded after the first `since`, as far as they are still buffered */
+    pub fn newer_than(&self, since: u32) -> impl Iterator<Item = &Event> {
+        let len = self.events.len();
+        let new = (self.recorded.wrapping_sub(since) as usize).min(len);
+        self.events.oldest_ordered().skip(len - new)
+    }
+
+    pub fn request_dump(&mut self) {
+        self.dump_requested = true;
+    }
+
+    pub fn take_dump_request(&mut self) -> bool {
+        core::mem::replace(&mut self.dump_requested, false)
+    }
+
+    /* One CSV line per event: time_ms,event,a,b */
+    pub fn dump<W: Write>(&self, w: &mut W) -> Result {
+        writeln!(w, "LOG BEGIN {}", self.events.len())?;
+        for event in self.events.oldest_ordered() {
+            match event.kind {
+                EventKind::SettingChanged { setting, value } => writeln!(
+                    w,
+                    "{},{},{},{}",
+                    event.time,
+                    event.kind.name(),
+                    setting.name(),
+                    value
+                )?,
+                kind => {
+                    let (_, a, b) = kind.encode();
+                    writeln!(w, "{},{},{},{}", event.time, kind.name(), a, b)?
+                }
+            }
+        }
+        writeln!(w, "LOG END")
+    }
+
+    pub fn serialize(&self, buf: &mut [u8; SERIALIZED_SIZE]) -> usize {
+        let mut len = 0;
+        for event in self.events.oldest_ordered() {
+            let (code, a, b) = event.kind.encode();
+            let record = &mut buf[len..len + RECORD_SIZE];
+            record[0] = code;
+            record[1..5].copy_from_slice(&event.time.to_le_bytes());
+            record[5..9].copy_from_slice(&a.to_le_bytes());
+            record[9..13].copy_from_slice(&b.to_le_bytes());
+            len += RECORD_SIZE;
+        }
+        len
+    }
+
+    pub fn deserialize(&mut self, buf: &[u8]) {
+        for record in buf.chunks_exact(RECORD_SIZE) {
+            let word = |i: usize| u32::from_le_bytes(record[i..i + 4].try_into().unwrap());
+            if let Some(kind) = EventKind::decode(record[0], word(5), word(9)) {
+                self.record(word(1), kind);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::String;
+
+    fn times(log: &EventLog, since: u32) -> heapless::Vec<u32, LOG_SIZE> {
+        log.newer_than(since).map(|event| event.time).collect()
+    }
+
+    #[test]
+    fn full_log_drops_oldest() {
+        let mut log = EventLog::new();
+        for time in 0..LOG_SIZE as u32 + 10 {
+            log.record(time, EventKind::Orgasm);
+        }
+        assert_eq!(log.recorded(), LOG_SIZE as u32 + 10);
+        let kept = times(&log, 0);
+        assert_eq!(kept.len(), LOG_SIZE);
+        assert_eq!(kept[0], 10);
+        assert_eq!(kept[LOG_SIZE - 1], LOG_SIZE as u32 + 9);
+    }
+
+    #[test]
+    fn newer_than_skips_seen_events() {
+        let mut log = EventLog::new();
+        assert_eq!(log.newer_than(0).count(), 0);
+        log.record(100, EventKind::Boot);
+        log.record(200, EventKind::SessionStart);
+        let seen = log.recorded();
+        log.record(300, EventKind::Orgasm);
+        assert_eq!(times(&log, seen), [300]);
+        assert_eq!(times(&log, log.recorded()), []);
+
+        /* Events pushed out before they were seen are gone */
+        for time in 1_000..1_000 + LOG_SIZE as u32 {
+            log.record(time, EventKind::CooldownEnd);
+        }
+        assert_eq!(log.newer_than(seen).count(), LOG_SIZE);
+    }
+
+    #[test]
+    fn serialized_log_restores() {
+        let mut log = EventLog::new();
+        log.record(1, EventKind::SessionStart);
+        log.record(
+            2,
+            EventKind::Edge {
+                area: 300,
+                peak: 40,
+            },
+        );
+        log.record(
+            3,
+            EventKind::SettingChanged {
+                setting: Setting::Cooldown,
+                value: 20_000,
+            },
+        );
+        log.record(
+            4,
+            EventKind::LimitReached {
+                setting: Setting::MaxSession,
+            },
+        );
+        let mut buf = [0u8; SERIALIZED_SIZE];
+        let len = log.serialize(&mut buf);
+        assert_eq!(len, 4 * RECORD_SIZE);
+
+        let mut restored = EventLog::new();
+        restored.deserialize(&buf[..len]);
+        assert_eq!(restored.recorded(), 4);
+        for (a, b) in log.newer_than(0).zip(restored.newer_than(0)) {
+            assert_eq!(a.time, b.time);
+            assert_eq!(a.kind.encode(), b.kind.encode());
+        }
+
+        /* Records with an unknown code are skipped */
+        buf[0] = 0xff;
+        let mut restored = EventLog::new();
+        restored.deserialize(&buf[..len]);
+        assert_eq!(times(&restored, 0), [2, 3, 4]);
+    }
+
+    #[test]
+    fn dump_is_csv() {
+        let mut log = EventLog::new();
+        log.record(10, EventKind::Boot);
+        log.record(
+            20,
+            EventKind::Edge {
+                area: 300,
+                peak: 40,
+            },
+        );
+        log.record(
+            30,
+            EventKind::SettingChanged {
+                setting: Setting::Cooldown,
+                value: 20_000,
+            },
+        );
+        let mut out = String::<256>::new();
+        log.dump(&mut out).unwrap();
+        assert_eq!(
+            out,
+            "LOG BEGIN 3\n\
+             10,boot,0,0\n\
+             20,edge,300,40\n\
+             30,setting_changed,cooldown,20000\n\
+             LOG END\n"
+        );
+    }
+
+    #[test]
+    fn sustained_peak_keeps_session_start() {
+        let mut state = crate::state::State::new();
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        let mut history = crate::history::Nogasm::<4>::new();
+        history.add(1_000_000, 1_000, &mut state);
+        let high = 1_000_000 + 4 * state.peak_value_thresh;
+        for time in (1_025..9_000).step_by(25) {
+            history.add(high, time, &mut state);
+        }
+        let kinds: heapless::Vec<&str, LOG_SIZE> = state
+            .log
+            .newer_than(0)
+            .map(|event| event.kind.name())
+            .collect();
+        assert_eq!(kinds, ["session_start", "edge", "cooldown_start"]);
+    }
+}
@@ -0,0 +1,70 @@
+use core::convert::Infallible;
+use core::fmt;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+/* A monochrome frame kept in memory, for drawing the screens away from the panel */
+pub struct FrameBuffer<const W: usize, const H: usize> {
+    pixels: [[bool; W]; H],
+}
+
+impl<const W: usize, const H: usize> FrameBuffer<W, H> {
+    pub fn new() -> FrameBuffer<W, H> {
+        FrameBuffer {
+            pixels: [[false; W]; H],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = [[false; W]; H];
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[y][x]
+    }
+}
+
+impl<const W: usize, const H: usize> Default for FrameBuffer<W, H> {
+    fn default() -> Self {
+        FrameBuffer::new()
+    }
+}
+
+impl<const W: usize, const H: usize> OriginDimensions for FrameBuffer<W, H> {
+    fn size(&self) -> Size {
+        Size::new(W as u32, H as u32)
+    }
+}
+
+impl<const W: usize, const H: usize> DrawTarget for FrameBuffer<W, H> {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        /* Like the panels, anything off the screen is dropped */
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) {
+                if x < W && y < H {
+                    self.pixels[y][x] = color.is_on();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/* One line per row, '#' for lit and '.' for dark pixels */
+impl<const W: usize, const H: usize> fmt::Display for FrameBuffer<W, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.pixels.iter() {
+            for &pixel in row.iter() {
+                f.write_str(if pixel { "#" } else { "." })?;
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use heapless::HistoryBuffer;
+
+/* 128 samples at 40Hz cover 3.2s, re-evaluated every 0.4s */
+const WINDOW: usize = 128;
+const HOP: usize = 16;
+
+/* 2*cos(2*pi*k/WINDOW) for bins k = 2, 3, 4 (0.625Hz, 0.94Hz, 1.25Hz), the contraction band */
+const BAND_COEFFS: [f32; 3] = [1.990_369_5, 1.978_353, 1.961_570_6];
+
+/* Sliding Goertzel over the filtered pressure, reporting the energy in the contraction band */
+pub struct Goertzel {
+    samples: HistoryBuffer<f32, WINDOW>,
+    since_update: usize,
+    band: u32,
+    power: f32,
+}
+
+impl Goertzel {
+    pub fn new() -> Goertzel {
+        Goertzel {
+            samples: HistoryBuffer::new(),
+            since_update: 0,
+            band: 0,
+            power: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, val: u32) {
+        self.samples.write(val as f32);
+        self.since_update += 1;
+        if self.samples.len() == WINDOW && self.since_update >= HOP {
+            self.since_update = 0;
+            self.update();
+        }
+    }
+
+    fn update(&mut self) {
+        let mean = self.samples.as_slice().iter().sum::<f32>() / WINDOW as f32;
+        let total: f32 = self
+            .samples
+            .as_slice()
+            .iter()
+            .map(|x| (x - mean) * (x - mean))
+            .sum();
+        self.power = total / WINDOW as f32;
+        if total <= 0.0 {
+            self.band = 0;
+            return;
+        }
+
+        let mut band = 0f32;
+        for coeff in BAND_COEFFS {
+            let mut s1 = 0f32;
+            let mut s2 = 0f32;
+            for x in self.samples.oldest_ordered() {
+                let s = (x - mean) + coeff * s1 - s2;
+                s2 = s1;
+                s1 = s;
+            }
+            band += s1 * s1 + s2 * s2 - coeff * s1 * s2;
+        }
+        /* By Parseval, the positive and negative bins together hold this share of the energy */
+        self.band = (2.0 * band / (WINDOW as f32 * total) * 1000.0).min(1000.0) as u32;
+    }
+
+    /* Share of the signal energy in the contraction band, in per mille */
+    pub fn band_energy(&self) -> u32 {
+        self.band
+    }
+
+    /* Mean square of the signal over the window */
+    pub fn power(&self) -> f32 {
+        self.power
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::Setting;
+    use core::f32::consts::PI;
+
+    const RATE: f32 = 40.0;
+
+    /* Feeds a full window of a baseline plus sinusoids of (frequency, amplitude) */
+    fn feed(waves: &[(f32, f32)]) -> Goertzel {
+        let mut goertzel = Goertzel::new();
+        for i in 0..WINDOW {
+            let t = i as f32 / RATE;
+            let wave: f32 = waves
+                .iter()
+                .map(|(freq, amp)| amp * (2.0 * PI * freq * t).sin())
+                .sum();
+            goertzel.add((100_000.0 + wave) as u32);
+        }
+        goertzel
+    }
+
+    /* Frequency of bin k */
+    fn bin(k: usize) -> f32 {
+        k as f32 * RATE / WINDOW as f32
+    }
+
+    #[test]
+    fn in_band_rhythm_stands_out() {
+        let threshold = Setting::RhythmThreshold.param().default;
+        let rhythm = feed(&[(1.0, 5_000.0)]);
+        assert!(
+            rhythm.band_energy() >= threshold,
+            "{}",
+            rhythm.band_energy()
+        );
+
+        for freq in [0.1, 3.0, 8.0] {
+            let other = feed(&[(freq, 5_000.0)]);
+            assert!(
+                other.band_energy() < threshold / 2,
+                "{}Hz: {}",
+                freq,
+                other.band_energy()
+            );
+        }
+        assert_eq!(feed(&[]).band_energy(), 0);
+    }
+
+    #[test]
+    fn band_energy_is_share_of_energy() {
+        /* All of a sinusoid centered on a band bin is in the band */
+        let full = feed(&[(bin(3), 5_000.0)]);
+        assert!((990..=1000).contains(&full.band_energy()));
+        /* Half of it when as much energy is outside */
+        let half = feed(&[(bin(3), 5_000.0), (bin(20), 5_000.0)]);
+        assert!(
+            (490..=510).contains(&half.band_energy()),
+            "{}",
+            half.band_energy()
+        );
+        /* The amplitude does not matter */
+        let small = feed(&[(bin(3), 50.0), (bin(20), 50.0)]);
+        assert!(small.band_energy().abs_diff(half.band_energy()) <= 20);
+
+        /* Mean square of a sinusoid is half its amplitude squared */
+        let power = full.power();
+        assert!((power - 12_500_000.0).abs() < 125_000.0, "{}", power);
+    }
+
+    #[test]
+    fn waits_for_a_full_window() {
+        let mut goertzel = Goertzel::new();
+        for i in 0..WINDOW - 1 {
+            let t = i as f32 / RATE;
+            goertzel.add((100_000.0 + 5_000.0 * (2.0 * PI * bin(3) * t).sin()) as u32);
+        }
+        assert_eq!(goertzel.band_energy(), 0);
+        goertzel.add(100_000);
+        assert!(goertzel.band_energy() > 0);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{avg::RunningAverage, state::State};
+use crate::{avg::RunningAverage, goertzel::Goertzel, orgasm::OrgasmDetector, state::State};
 use log::debug;
 
 #[derive(Copy, Clone)]
@@ -25,6 +25,9 @@ pub struct Nogasm<const AVG_SAMPLES: usize> {
     pub min: u32,
     pub min_decay: u32,
     state: PeakState,
+    peak: u32,
+    orgasm: OrgasmDetector,
+    rhythm: Goertzel,
 }
 
 impl<const AVG_SAMPLES: usize> Nogasm<{ AVG_SAMPLES }> {
@@ -34,6 +37,9 @@ impl<const AVG_SAMPLES: usize> Nogasm<{ AVG_SAMPLES }> {
             min: u32::MAX,
             min_decay: u32::MAX,
             state: PeakState::None,
+            peak: 0,
+            orgasm: OrgasmDetector::new(),
+            rhythm: Goertzel::new(),
         }
     }
 
@@ -54,13 +60,20 @@ impl<const AVG_SAMPLES: usize> Nogasm<{ AVG_SAMPLES }> {
 
         debug!("Current value: {}", val);
         // let cur = val as u32;
+        self.rhythm.add(cur);
 
         self.state = match self.state {
+            /* Ignore residual contractions right after resuming */
+            _ if state.in_grace(time) || !state.detection_enabled() => None,
             None if cur >= state.peak_value_thresh => In { area: cur },
             In { area } if area > state.peak_area_threshold => {
                 debug!("Max area reached");
                 self.min = u32::MAX;
-                state.hysteresis.enter(time);
+                /* A peak that lasts into the cooldown is still the same edge */
+                match state.hysteresis.is_active(time) {
+                    true => state.hysteresis.enter(time, state.hysteresis.duration()),
+                    false => state.edge(time, area, self.peak),
+                }
                 None
             }
             In { area } if cur >= state.peak_value_thresh => {
@@ -98,8 +111,35 @@ impl<const AVG_SAMPLES: usize> Nogasm<{ AVG_SAMPLES }> {
             }
             default => default,
         };
+        self.peak = match self.state {
+            None => 0,
+            _ => self.peak.max(cur),
+        };
+
+        /* Periodic contractions are an early warning, even before the area threshold is reached */
+        let rhythm_gate = (state.peak_value_thresh / 4) as f32;
+        if state.rhythm_warning
+            && state.detection_enabled()
+            && !state.hysteresis.is_active(time)
+            && self.rhythm.band_energy() >= state.rhythm_thresh
+            && self.rhythm.power() >= rhythm_gate * rhythm_gate
+        {
+            debug!(
+                "Rhythmic contractions, band energy {}",
+                self.rhythm.band_energy()
+            );
+            self.min = u32::MAX;
+            state.edge(time, self.get_area(), self.peak);
+            self.state = None;
+        }
+
+        state.area = self.get_area();
 
-        let stop = state.hysteresis.is_active(time, state.cooldown_time);
+        if self.orgasm.add(cur, time, state) {
+            state.orgasm(time);
+        }
+
+        let stop = state.hysteresis.is_active(time);
         debug!("Hysteresis result (should stop?): {}", stop);
 
         if stop {
@@ -109,10 +149,22 @@ impl<const AVG_SAMPLES: usize> Nogasm<{ AVG_SAMPLES }> {
         }
     }
 
+    /* Forget the baseline so it is learned again from the next sample */
+    pub fn calibrate(&mut self) {
+        self.min = u32::MAX;
+        self.min_decay = u32::MAX;
+        self.state = PeakState::None;
+        self.peak = 0;
+    }
+
     pub fn get_current_value(&self) -> u32 {
         self.avg.get().saturating_sub(self.min_decay)
     }
 
+    pub fn get_rhythm(&self) -> u32 {
+        self.rhythm.band_energy()
+    }
+
     pub fn get_area(&self) -> u32 {
         use PeakState::*;
         match self.state {
@@ -126,3 +178,33 @@ impl<const AVG_SAMPLES: usize> Nogasm<{ AVG_SAMPLES }> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cooldown::CooldownPolicy;
+
+    #[test]
+    fn sustained_peak_is_one_edge() {
+        let mut state = State::new();
+        state.cooldown_policy = CooldownPolicy::Exponential;
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        let mut history = Nogasm::<4>::new();
+        history.add(1_000_000, 1_000, &mut state);
+        let high = 1_000_000 + 4 * state.peak_value_thresh;
+        for time in (1_025..9_000).step_by(25) {
+            history.add(high, time, &mut state);
+        }
+
+        assert_eq!(state.stats.edges, 1);
+        assert_eq!(state.cooldown.edges, 1);
+        /* The cooldown started out at its base and is still running */
+        assert_eq!(state.hysteresis.duration(), state.cooldown_time);
+        assert!(state.hysteresis.is_active(9_000));
+        assert_eq!(
+            state.next_cooldown(9_000),
+            state.cooldown_time * state.cooldown_factor / 100
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::state::State;
+use log::info;
+
+#[derive(Copy, Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum InsertionState {
+    Removed,
+    Settling { since: u32 },
+    Inserted,
+    Removing { since: u32 },
+}
+
+pub enum InsertionResult {
+    None,
+    Inserted,
+    Removed,
+}
+
+pub struct Insertion {
+    pub ambient: u32,
+    pub baseline: u32,
+    state: InsertionState,
+}
+
+impl Insertion {
+    pub fn new() -> Insertion {
+        Insertion {
+            ambient: u32::MAX,
+            baseline: u32::MAX,
+            state: InsertionState::Removed,
+        }
+    }
+
+    /* Forget the ambient pressure, it is learned again while removed */
+    pub fn calibrate(&mut self) {
+        *self = Insertion::new();
+    }
+
+    pub fn add(&mut self, val: u32, time: u32, state: &State) -> InsertionResult {
+        use InsertionState::*;
+        if self.baseline == u32::MAX {
+            self.baseline = val;
+            self.ambient = val;
+        }
+        self.baseline = (self.baseline * 31 + val) / 32;
+
+        let inserted_level = self.ambient.saturating_add(state.insertion_thresh);
+        let removed_level = self.ambient.saturating_add(state.insertion_thresh / 2);
+
+        let mut res = InsertionResult::None;
+        self.state = match self.state {
+            Removed if self.baseline >= inserted_level => {
+                info!("Pressure rise detected, settling");
+                Settling { since: time }
+            }
+            Removed => {
+                /* Only learn the ambient pressure while nothing is inserted */
+                self.ambient = (self.ambient * 199 + self.baseline) / 200;
+                Removed
+            }
+            Settling { .. } if self.baseline < inserted_level => {
+                info!("Pressure rise was not stable");
+                Removed
+            }
+            Settling { since } if time.wrapping_sub(since) >= state.insertion_settle_time => {
+                info!("Sensor inserted (baseline {}, ambient {})", self.baseline, self.ambient);
+                res = InsertionResult::Inserted;
+                Inserted
+            }
+            Inserted if self.baseline < removed_level => Removing { since: time },
+            Removing { .. } if self.baseline >= removed_level => Inserted,
+            Removing { since } if time.wrapping_sub(since) >= state.removal_time => {
+                info!("Sensor removed (baseline {}, ambient {})", self.baseline, self.ambient);
+                res = InsertionResult::Removed;
+                Removed
+            }
+            default => default,
+        };
+        res
+    }
+}
@@ -0,0 +1,138 @@
+use crate::param::{Setting, RECORD_SIZE};
+use crate::profile::PROFILES;
+use crate::state::MAX_INTENSITY;
+
+/* Ceilings are stored as records above the setting indices, one per profile */
+const CEILING_RECORD: u8 = 0x80;
+const SCALAR_LIMITS: [Setting; 4] = [
+    Setting::MaxSession,
+    Setting::MaxStimulation,
+    Setting::BreakAfter,
+    Setting::BreakLength,
+];
+pub const SERIALIZED_SIZE: usize = (SCALAR_LIMITS.len() + PROFILES.len()) * RECORD_SIZE;
+/* Shortest pause of the continuous stimulation limit, however short the cooldown is set */
+pub const MIN_STIM_PAUSE: u32 = 30_000;
+
+/* Hard bounds on stimulation, stored apart from the regular settings. A time of 0 is no limit */
+pub struct Limits {
+    pub max_session: u32,
+    pub max_stimulation: u32,
+    /* Stimulation time after which a break is taken */
+    pub break_after: u32,
+    pub break_length: u32,
+    /* Highest output per profile, whatever the intensity setting says */
+    pub ceilings: [u8; PROFILES.len()],
+    /* Stimulation since the last break, and the pause that is counting towards one */
+    stimulated: u32,
+    paused: u32,
+    last_update: u32,
+    break_until: Option<u32>,
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits {
+            max_session: 0,
+            max_stimulation: 0,
+            break_after: 0,
+            break_length: 0,
+            ceilings: [MAX_INTENSITY; PROFILES.len()],
+            stimulated: 0,
+            paused: 0,
+            last_update: 0,
+            break_until: None,
+        }
+    }
+
+    pub fn ceiling(&self, profile: usize) -> u8 {
+        self.ceilings[profile % PROFILES.len()]
+    }
+
+    pub fn on_break(&self, time: u32) -> bool {
+        matches!(self.break_until, Some(until) if (time.wrapping_sub(until) as i32) < 0)
+    }
+
+    /* Counts stimulation towards the next break, true once a break is due. Pausing for
+     * a whole break on one's own counts as one, so stopping a session does not skip it */
+    pub fn track(&mut self, time: u32, stimulating: bool) -> bool {
+        let elapsed = time.wrapping_sub(self.last_update);
+        self.last_update = time;
+        if !stimulating {
+            self.paused = self.paused.saturating_add(elapsed);
+            if self.paused >= self.break_length {
+                self.stimulated = 0;
+            }
+            return false;
+        }
+        self.paused = 0;
+        self.stimulated = self.stimulated.saturating_add(elapsed);
+        if self.break_after == 0 || self.stimulated < self.break_after {
+            return false;
+        }
+        self.stimulated = 0;
+        self.break_until = Some(time.wrapping_add(self.break_length));
+        true
+    }
+
+    pub fn get(&self, setting: Setting) -> Option<u32> {
+        Some(match setting {
+            Setting::MaxSession => self.max_session,
+            Setting::MaxStimulation => self.max_stimulation,
+            Setting::BreakAfter => self.break_after,
+            Setting::BreakLength => self.break_length,
+            _ => return None,
+        })
+    }
+
+    fn put(&mut self, setting: Setting, value: u32) {
+        match setting {
+            Setting::MaxSession => self.max_session = value,
+            Setting::MaxStimulation => self.max_stimulation = value,
+            Setting::BreakAfter => self.break_after = value,
+            Setting::BreakLength => self.break_length = value,
+            _ => {}
+        }
+    }
+
+    /* Same (index, value) records as the settings, under their own key */
+    pub fn serialize(&self, buf: &mut [u8; SERIALIZED_SIZE]) -> usize {
+        let scalars = SCALAR_LIMITS
+            .iter()
+            .map(|&setting| (setting as u8, self.get(setting).unwrap_or(0)));
+        let ceilings = self
+            .ceilings
+            .iter()
+            .enumerate()
+            .map(|(profile, &ceiling)| (CEILING_RECORD + profile as u8, ceiling as u32));
+        for ((index, value), record) in scalars
+            .chain(ceilings)
+            .zip(buf.chunks_exact_mut(RECORD_SIZE))
+        {
+            record[0] = index;
+            record[1..5].copy_from_slice(&value.to_le_bytes());
+        }
+        SERIALIZED_SIZE
+    }
+
+    pub fn deserialize(&mut self, buf: &[u8]) {
+        for record in buf.chunks_exact(RECORD_SIZE) {
+            let value = u32::from_le_bytes(record[1..5].try_into().unwrap());
+            match record[0] {
+                index if index >= CEILING_RECORD => {
+                    if let Some(ceiling) = self.ceilings.get_mut((index - CEILING_RECORD) as usize)
+                    {
+                        *ceiling = value.min(MAX_INTENSITY as u32) as u8;
+                    }
+                }
+                index => {
+                    if let Some(setting) = Setting::from_index(index as u32) {
+                        if SCALAR_LIMITS.contains(&setting) {
+                            self.put(setting, setting.param().clamp(value));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+/* Unlock codes are entered digit by digit with the encoder */
+pub const CODE_DIGITS: usize = 4;
+pub const MAX_CODE: u32 = 9_999;
+/* Wrong codes can not be retried right away, so guessing all of them takes hours */
+const RETRY_DELAY: u32 = 5_000;
+/* How often the time left on a lock timer is saved, so a reboot does not reset it */
+const SAVE_INTERVAL: u32 = 60_000;
+/* Code and time left, a code above MAX_CODE stands for unlocked and no time left for no timer */
+pub const SERIALIZED_SIZE: usize = 8;
+
+/* Keyholder mode: settings are read-only and sessions can not be stopped until unlocked */
+pub struct Lock {
+    code: Option<u32>,
+    expires_at: Option<u32>,
+    retry_at: u32,
+    /* Whether the lock changed since it was last saved, and when that was */
+    changed: bool,
+    saved_at: u32,
+}
+
+impl Lock {
+    pub fn new() -> Lock {
+        Lock {
+            code: None,
+            expires_at: None,
+            retry_at: 0,
+            changed: false,
+            saved_at: 0,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.code.is_some()
+    }
+
+    /* A duration of 0 locks until the code is entered */
+    pub fn lock(&mut self, code: u32, time: u32, duration: u32) -> bool {
+        if self.is_locked() || code > MAX_CODE {
+            return false;
+        }
+        self.code = Some(code);
+        self.expires_at = match duration {
+            0 => None,
+            duration => Some(time.wrapping_add(duration)),
+        };
+        self.changed = true;
+        true
+    }
+
+    pub fn unlock(&mut self, code: u32, time: u32) -> bool {
+        if (time.wrapping_sub(self.retry_at) as i32) < 0 {
+            return false;
+        }
+        if self.code != Some(code) {
+            self.retry_at = time.wrapping_add(RETRY_DELAY);
+            return false;
+        }
+        self.clear();
+        true
+    }
+
+    fn clear(&mut self) {
+        self.code = None;
+        self.expires_at = None;
+        self.changed = true;
+    }
+
+    /* Time left until the lock expires on its own */
+    pub fn remaining(&self, time: u32) -> Option<u32> {
+        let left = self.expires_at?.wrapping_sub(time) as i32;
+        Some(left.max(0) as u32)
+    }
+
+    /* Unlocks once the timer ran out, true on the call that did it */
+    pub fn expire(&mut self, time: u32) -> bool {
+        if self.remaining(time) != Some(0) {
+            return false;
+        }
+        self.clear();
+        true
+    }
+
+    /* True once the lock changed, and every so often while its timer runs */
+    pub fn take_save(&mut self, time: u32) -> bool {
+        let timer_due =
+            self.expires_at.is_some() && time.wrapping_sub(self.saved_at) >= SAVE_INTERVAL;
+        if !self.changed && !timer_due {
+            return false;
+        }
+        self.changed = false;
+        self.saved_at = time;
+        true
+    }
+
+    pub fn serialize(&self, time: u32, buf: &mut [u8; SERIALIZED_SIZE]) -> usize {
+        let code = self.code.unwrap_or(u32::MAX);
+        /* A timer that ran out keeps a millisecond, as no time left means no timer */
+        let left = self.remaining(time).map_or(0, |left| left.max(1));
+        buf[0..4].copy_from_slice(&code.to_le_bytes());
+        buf[4..8].copy_from_slice(&left.to_le_bytes());
+        SERIALIZED_SIZE
+    }
+
+    /* Restores a saved lock, its timer carries on from the time left when it was saved */
+    pub fn deserialize(&mut self, buf: &[u8], time: u32) {
+        if buf.len() != SERIALIZED_SIZE {
+            return;
+        }
+        let code = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let left = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        if code > MAX_CODE {
+            return;
+        }
+        self.code = Some(code);
+        self.expires_at = match left {
+            0 => None,
+            left => Some(time.wrapping_add(left)),
+        };
+        self.saved_at = time;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(lock: &Lock, time: u32, boot_time: u32) -> Lock {
+        let mut buf = [0u8; SERIALIZED_SIZE];
+        let len = lock.serialize(time, &mut buf);
+        let mut restored = Lock::new();
+        restored.deserialize(&buf[..len], boot_time);
+        restored
+    }
+
+    #[test]
+    fn saved_lock_survives_reboot() {
+        let mut lock = Lock::new();
+        assert!(!roundtrip(&lock, 0, 0).is_locked());
+
+        assert!(lock.lock(1234, 1_000, 0));
+        let mut restored = roundtrip(&lock, 5_000, 100);
+        assert!(restored.is_locked() && restored.remaining(100).is_none());
+        assert!(!restored.unlock(1111, 200));
+        assert!(restored.unlock(1234, 10_000));
+    }
+
+    #[test]
+    fn saved_timer_keeps_time_left() {
+        let mut lock = Lock::new();
+        assert!(lock.lock(42, 1_000, 60_000));
+        let mut restored = roundtrip(&lock, 31_000, 500);
+        assert_eq!(restored.remaining(500), Some(30_000));
+        assert!(!restored.expire(30_000));
+        assert!(restored.expire(30_500));
+    }
+
+    #[test]
+    fn saves_changes_and_running_timers() {
+        let mut lock = Lock::new();
+        assert!(!lock.take_save(0));
+        lock.lock(42, 1_000, 0);
+        assert!(lock.take_save(1_000));
+        /* Without a timer there is nothing to keep up to date */
+        assert!(!lock.take_save(1_000 + SAVE_INTERVAL));
+        lock.unlock(42, 2_000);
+        assert!(lock.take_save(2_000));
+
+        lock.lock(42, 3_000, 600_000);
+        assert!(lock.take_save(3_000));
+        assert!(!lock.take_save(4_000));
+        assert!(lock.take_save(3_000 + SAVE_INTERVAL));
+    }
+}
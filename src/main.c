@@ -10,7 +10,7 @@
 #include "nogasm.h"
 #include "nvs_flash.h"
 
-#define RS_HEAP_SIZE 1 * 1024
+#define RS_HEAP_SIZE 16 * 1024
 
 #define GPIO_LED GPIO_NUM_2
 
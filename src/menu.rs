@@ -1,18 +1,211 @@
+use heapless::Vec;
+
+use crate::command::Command;
+use crate::lock::CODE_DIGITS;
+use crate::param::{ParamKind, Setting};
 use crate::state::*;
 
+/* Rows of a list that fit on the 128x32 screen */
+pub const VISIBLE_ROWS: usize = 3;
+const MAX_DEPTH: usize = 4;
+/* Steps of one detent while the switch is held */
+pub const COARSE_STEPS: i32 = 10;
+
+pub enum Item {
+    Param(Setting),
+    Page(&'static Page),
+    Action(&'static str, Command),
+    Summary,
+    /* Enters the code that locks or unlocks the settings */
+    Lock,
+}
+
+/* A list of items, shown below a row leading back to the parent */
+pub struct Page {
+    pub title: &'static str,
+    pub items: &'static [Item],
+}
+
+impl Page {
+    /* The back row plus the items */
+    pub fn rows(&self) -> usize {
+        self.items.len() + 1
+    }
+}
+
+pub static ROOT: Page = Page {
+    title: "Home",
+    items: &[
+        Item::Page(&DETECTION),
+        Item::Page(&COOLDOWN),
+        Item::Page(&STIMULATION),
+        Item::Page(&SESSION),
+        Item::Page(&LIMITS),
+        Item::Page(&SYSTEM),
+    ],
+};
+
+static DETECTION: Page = Page {
+    title: "Detection",
+    items: &[
+        Item::Param(Setting::Sensitivity),
+        Item::Param(Setting::Density),
+        Item::Param(Setting::Duration),
+        Item::Param(Setting::RhythmWarning),
+        Item::Param(Setting::RhythmThreshold),
+        Item::Page(&ORGASM),
+        Item::Action("Calibrate", Command::Calibrate),
+    ],
+};
+
+static ORGASM: Page = Page {
+    title: "Orgasm",
+    items: &[
+        Item::Param(Setting::OrgasmAction),
+        Item::Param(Setting::RuinTime),
+        Item::Param(Setting::OrgasmThreshold),
+        Item::Param(Setting::OrgasmContractions),
+        Item::Param(Setting::OrgasmMinInterval),
+        Item::Param(Setting::OrgasmMaxInterval),
+    ],
+};
+
+static COOLDOWN: Page = Page {
+    title: "Cooldown",
+    items: &[
+        Item::Param(Setting::Cooldown),
+        Item::Param(Setting::CooldownPolicy),
+        Item::Param(Setting::CooldownIncrement),
+        Item::Param(Setting::CooldownFactor),
+        Item::Param(Setting::CooldownMax),
+        Item::Param(Setting::CooldownReset),
+        Item::Param(Setting::RampTime),
+        Item::Param(Setting::RampCurve),
+        Item::Param(Setting::Grace),
+    ],
+};
+
+static STIMULATION: Page = Page {
+    title: "Stimulation",
+    items: &[
+        Item::Param(Setting::Intensity),
+        Item::Param(Setting::Profile),
+        Item::Page(&TEASE),
+        Item::Page(&AUTO_RAMP),
+    ],
+};
+
+static TEASE: Page = Page {
+    title: "Tease",
+    items: &[
+        Item::Param(Setting::Tease),
+        Item::Param(Setting::TeaseCooldownMin),
+        Item::Param(Setting::TeaseCooldownMax),
+        Item::Param(Setting::TeaseIntensityMin),
+        Item::Param(Setting::TeaseIntensityMax),
+        Item::Param(Setting::TeaseCutChance),
+    ],
+};
+
+static AUTO_RAMP: Page = Page {
+    title: "Auto ramp",
+    items: &[
+        Item::Param(Setting::AutoRamp),
+        Item::Param(Setting::AutoRampRate),
+        Item::Param(Setting::AutoRampFloor),
+        Item::Param(Setting::AutoRampCeiling),
+        Item::Param(Setting::AutoRampBackoff),
+    ],
+};
+
+static SESSION: Page = Page {
+    title: "Session",
+    items: &[
+        Item::Param(Setting::Program),
+        Item::Param(Setting::ProgramEdgeGoal),
+        Item::Param(Setting::ProgramDuration),
+        Item::Param(Setting::AutoStart),
+        Item::Param(Setting::InsertionThreshold),
+        Item::Param(Setting::InsertionSettle),
+        Item::Param(Setting::RemovalTime),
+        Item::Summary,
+    ],
+};
+
+static LIMITS: Page = Page {
+    title: "Limits",
+    items: &[
+        Item::Param(Setting::MaxSession),
+        Item::Param(Setting::MaxStimulation),
+        Item::Param(Setting::BreakAfter),
+        Item::Param(Setting::BreakLength),
+        Item::Param(Setting::IntensityCeiling),
+    ],
+};
+
+static SYSTEM: Page = Page {
+    title: "System",
+    items: &[
+        Item::Lock,
+        Item::Param(Setting::LockTime),
+        Item::Param(Setting::LogToFlash),
+        Item::Param(Setting::Telemetry),
+        Item::Param(Setting::EncoderInvert),
+        Item::Param(Setting::EncoderAcceleration),
+    ],
+};
+
+pub struct Level {
+    pub page: &'static Page,
+    /* Selected row, 0 is the back row */
+    pub cursor: usize,
+    /* First row on the screen */
+    pub top: usize,
+}
+
+impl Level {
+    fn new(page: &'static Page) -> Level {
+        Level {
+            page,
+            cursor: 0,
+            top: 0,
+        }
+    }
+
+    /* Moves the cursor and scrolls just enough to keep it on a screen of `rows` rows */
+    fn select(&mut self, cursor: usize, rows: usize) {
+        self.cursor = cursor.min(self.page.rows() - 1);
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        } else if self.cursor >= self.top + rows {
+            self.top = self.cursor + 1 - rows;
+        }
+    }
+
+    /* The item of a row, None for the back row */
+    pub fn item(&self, row: usize) -> Option<&'static Item> {
+        row.checked_sub(1).map(|index| &self.page.items[index])
+    }
+}
+
+/* A code being entered, one digit per click */
+#[derive(Copy, Clone, PartialEq, Default)]
+pub struct CodeEntry {
+    pub code: u32,
+    pub entered: usize,
+    /* The digit being chosen with the encoder */
+    pub digit: u32,
+}
+
 #[derive(PartialEq)]
 pub enum MenuPosition {
     Main,
-    Peak,
-    PeakSelect,
-    Area,
-    AreaSelect,
-    Duration,
-    DurationSelect,
-    Intensity,
-    IntensitySelect,
-    Cooldown,
-    CooldownSelect,
+    /* The page on top of the stack */
+    List,
+    /* A parameter's screen, turning changes its value */
+    Param(Setting),
+    Summary(usize),
+    Code(CodeEntry),
 }
 
 impl Default for MenuPosition {
@@ -23,103 +216,354 @@ impl Default for MenuPosition {
 
 pub struct Menu {
     pub position: MenuPosition,
+    stack: Vec<Level, MAX_DEPTH>,
+    /* List rows the display has room for */
+    rows: usize,
+    was_running: bool,
 }
 
 impl Default for Menu {
     fn default() -> Self {
+        Menu::with_rows(VISIBLE_ROWS)
+    }
+}
+
+impl Menu {
+    pub fn with_rows(rows: usize) -> Menu {
         Menu {
             position: MenuPosition::default(),
+            stack: Vec::new(),
+            rows: rows.max(1),
+            was_running: false,
         }
     }
-}
 
-impl Menu {
-    pub fn backward(&mut self, state: &mut State) {
-        use MenuPosition::*;
-        self.position = match self.position {
-            Main => Intensity,
-            Peak => Main,
-            Area => Peak,
-            Duration => Area,
-            Cooldown => Duration,
-            Intensity => Cooldown,
-            PeakSelect => {
-                state.peak_down();
-                PeakSelect
-            }
-            AreaSelect => {
-                state.area_down();
-                AreaSelect
-            }
-            DurationSelect => {
-                state.duration_down();
-                DurationSelect
-            }
-            IntensitySelect => {
-                state.intensity_down();
-                IntensitySelect
-            }
-            CooldownSelect => {
-                state.cooldown_down();
-                CooldownSelect
-            }
+    /* Show the session summary once a session stops */
+    pub fn update(&mut self, state: &State) {
+        if self.was_running && !state.running {
+            self.stack.clear();
+            self.position = MenuPosition::Summary(0);
         }
+        self.was_running = state.running;
     }
-    pub fn foward(&mut self, state: &mut State) {
+
+    /* The list being shown, or returned to from a parameter or the summary */
+    pub fn level(&self) -> Option<&Level> {
+        self.stack.last()
+    }
+
+    fn open(&mut self, page: &'static Page) {
+        let mut level = Level::new(page);
+        level.select(1, self.rows);
+        if self.stack.push(level).is_ok() {
+            self.position = MenuPosition::List;
+        }
+    }
+
+    /* Back to the list, or to the main screen if there is none */
+    fn close(&mut self) {
+        self.position = match self.stack.is_empty() {
+            true => MenuPosition::Main,
+            false => MenuPosition::List,
+        };
+    }
+
+    /* Back to the main screen from anywhere */
+    pub fn home(&mut self) -> Option<Command> {
+        let position = core::mem::take(&mut self.position);
+        self.stack.clear();
+        match position {
+            MenuPosition::Param(Setting::Intensity) => Some(Command::ManualStim(false)),
+            _ => None,
+        }
+    }
+
+    /* Encoder steps, positive is clockwise */
+    pub fn turn(&mut self, state: &State, steps: i32) -> Option<Command> {
         use MenuPosition::*;
-        self.position = match self.position {
-            Main => Peak,
-            Peak => Area,
-            Area => Duration,
-            Duration => Cooldown,
-            Cooldown => Intensity,
-            Intensity => Main,
-            PeakSelect => {
-                state.peak_up();
-                PeakSelect
-            }
-            AreaSelect => {
-                state.area_up();
-                AreaSelect
-            }
-            DurationSelect => {
-                state.duration_up();
-                DurationSelect
-            }
-            IntensitySelect => {
-                state.intensity_up();
-                IntensitySelect
+        if state.emergency_stopped {
+            return None;
+        }
+        match self.position {
+            Main => self.open(&ROOT),
+            List => {
+                let rows = self.rows;
+                let level = self.stack.last_mut()?;
+                level.select(level.cursor.saturating_add_signed(steps as isize), rows);
             }
-            CooldownSelect => {
-                state.cooldown_up();
-                CooldownSelect
+            Param(setting) => return Some(Command::Adjust(setting, steps)),
+            Summary(line) => {
+                let last = state.stats.summary_lines().saturating_sub(1);
+                self.position = Summary(line.saturating_add_signed(steps as isize).min(last));
             }
+            Code(ref mut entry) => entry.digit = (entry.digit as i32 + steps).rem_euclid(10) as u32,
         }
+        None
     }
-    pub fn click(&mut self, state: &mut State) {
+
+    pub fn click(&mut self, state: &State) -> Option<Command> {
         use MenuPosition::*;
-        self.position = match self.position {
-            Main => {
-                state.toggle();
-                Main
+        /* The emergency stop screen covers everything until it is acknowledged */
+        if state.emergency_stopped {
+            self.home();
+            return Some(Command::AcknowledgeStop);
+        }
+        match self.position {
+            Main => return Some(Command::Toggle),
+            List => {
+                let level = self.level()?;
+                match level.item(level.cursor) {
+                    None => {
+                        self.stack.pop();
+                        self.close();
+                    }
+                    Some(Item::Page(page)) => self.open(page),
+                    Some(Item::Action(_, command)) => return Some(*command),
+                    Some(Item::Summary) => self.position = Summary(0),
+                    Some(Item::Lock) => self.position = Code(CodeEntry::default()),
+                    /* Editing the intensity drives the toy by hand, so it needs one */
+                    Some(Item::Param(Setting::Intensity)) if !state.ble_connected => {}
+                    Some(Item::Param(Setting::Intensity)) => {
+                        self.position = Param(Setting::Intensity);
+                        return Some(Command::ManualStim(true));
+                    }
+                    /* Toggles show their value in the list, a click flips them in place */
+                    Some(Item::Param(setting))
+                        if matches!(setting.param().kind, ParamKind::Toggle) =>
+                    {
+                        return Some(Command::Cycle(*setting));
+                    }
+                    Some(Item::Param(setting)) => self.position = Param(*setting),
+                }
             }
-            Peak => PeakSelect,
-            Area => AreaSelect,
-            Duration => DurationSelect,
-            Cooldown => CooldownSelect,
-            Intensity if state.ble_connected => {
-                state.start_stim_manual();
-                IntensitySelect
+            Param(Setting::Intensity) => {
+                self.close();
+                return Some(Command::ManualStim(false));
             }
-            Intensity => Intensity,
-            PeakSelect => Peak,
-            AreaSelect => Area,
-            DurationSelect => Duration,
-            CooldownSelect => Cooldown,
-            IntensitySelect => {
-                state.stop_stim_manual();
-                Intensity
+            Param(_) | Summary(_) => self.close(),
+            Code(mut entry) => {
+                entry.code = entry.code * 10 + entry.digit;
+                entry.entered += 1;
+                entry.digit = 0;
+                if entry.entered < CODE_DIGITS {
+                    self.position = Code(entry);
+                    return None;
+                }
+                self.close();
+                return Some(match state.lock.is_locked() {
+                    true => Command::Unlock(entry.code),
+                    false => Command::Lock(entry.code),
+                });
             }
         }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::dispatch;
+
+    /* Title of the page on top and the selected row */
+    fn at(menu: &Menu) -> (&'static str, usize) {
+        let level = menu.level().unwrap();
+        (level.page.title, level.cursor)
+    }
+
+    #[test]
+    fn opens_and_leaves_pages() {
+        let state = State::new();
+        let mut menu = Menu::default();
+        assert_eq!(menu.click(&state), Some(Command::Toggle));
+        assert!(menu.level().is_none());
+
+        /* Turning on the main screen opens the root on its first item */
+        assert_eq!(menu.turn(&state, 1), None);
+        assert!(menu.position == MenuPosition::List);
+        assert_eq!(at(&menu), ("Home", 1));
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Detection", 1));
+        menu.turn(&state, 5);
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Orgasm", 1));
+
+        /* The back row leads up one page at a time, then to the main screen */
+        menu.turn(&state, -1);
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Detection", 6));
+        menu.turn(&state, -10);
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Home", 1));
+        menu.turn(&state, -1);
+        menu.click(&state);
+        assert!(menu.position == MenuPosition::Main && menu.level().is_none());
+    }
+
+    #[test]
+    fn scrolls_and_clamps() {
+        let state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        menu.turn(&state, 2);
+        assert_eq!(at(&menu), ("Home", 3));
+        assert_eq!(menu.level().unwrap().top, 1);
+        /* Past the last row stays on it */
+        menu.turn(&state, 100);
+        assert_eq!(at(&menu), ("Home", ROOT.rows() - 1));
+        assert_eq!(menu.level().unwrap().top, ROOT.rows() - VISIBLE_ROWS);
+        menu.turn(&state, -100);
+        assert_eq!(at(&menu), ("Home", 0));
+        assert_eq!(menu.level().unwrap().top, 0);
+
+        /* Taller screens scroll later */
+        let mut menu = Menu::with_rows(5);
+        menu.turn(&state, 1);
+        menu.turn(&state, 3);
+        assert_eq!(menu.level().unwrap().top, 0);
+    }
+
+    #[test]
+    fn settings_turn_into_commands() {
+        let state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        menu.turn(&state, 1);
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Cooldown", 1));
+        menu.click(&state);
+        assert!(menu.position == MenuPosition::Param(Setting::Cooldown));
+        assert_eq!(
+            menu.turn(&state, -3),
+            Some(Command::Adjust(Setting::Cooldown, -3))
+        );
+        assert_eq!(menu.click(&state), None);
+        assert_eq!(at(&menu), ("Cooldown", 1));
+
+        /* Actions hand their command over */
+        menu.turn(&state, -1);
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Home", 2));
+        menu.turn(&state, -1);
+        menu.click(&state);
+        menu.turn(&state, 6);
+        assert_eq!(menu.click(&state), Some(Command::Calibrate));
+    }
+
+    #[test]
+    fn intensity_drives_toy_by_hand() {
+        let mut state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        menu.turn(&state, 2);
+        menu.click(&state);
+        assert_eq!(at(&menu), ("Stimulation", 1));
+        /* Not without a toy */
+        assert_eq!(menu.click(&state), None);
+        assert!(menu.position == MenuPosition::List);
+
+        state.set_ble_connected(true);
+        assert_eq!(menu.click(&state), Some(Command::ManualStim(true)));
+        assert_eq!(menu.click(&state), Some(Command::ManualStim(false)));
+        menu.click(&state);
+        assert_eq!(menu.home(), Some(Command::ManualStim(false)));
+    }
+
+    #[test]
+    fn double_click_goes_home() {
+        let state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        menu.click(&state);
+        menu.click(&state);
+        assert!(menu.position == MenuPosition::Param(Setting::Sensitivity));
+        assert_eq!(menu.home(), None);
+        assert!(menu.position == MenuPosition::Main && menu.level().is_none());
+        /* The next turn starts at the root again */
+        menu.turn(&state, 1);
+        assert_eq!(at(&menu), ("Home", 1));
+    }
+
+    #[test]
+    fn summary_after_session() {
+        let mut state = State::new();
+        let mut menu = Menu::default();
+        state.toggle();
+        menu.update(&state);
+        menu.turn(&state, 1);
+        menu.click(&state);
+        state.edge(1_000, 300_000, 5_000);
+        state.cur_time_ms = 2_000;
+        state.toggle();
+        menu.update(&state);
+        assert!(menu.position == MenuPosition::Summary(0) && menu.level().is_none());
+
+        menu.turn(&state, 3);
+        assert!(menu.position == MenuPosition::Summary(3));
+        menu.turn(&state, 100);
+        let last = state.stats.summary_lines() - 1;
+        assert!(menu.position == MenuPosition::Summary(last));
+        menu.click(&state);
+        assert!(menu.position == MenuPosition::Main);
+        /* Only a session that stops leads there */
+        menu.update(&state);
+        assert!(menu.position == MenuPosition::Main);
+    }
+
+    #[test]
+    fn code_entry() {
+        let mut state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        menu.turn(&state, 5);
+        menu.click(&state);
+        assert_eq!(menu.click(&state), None);
+        for digit in [1, 2, 3] {
+            menu.turn(&state, digit);
+            assert_eq!(menu.click(&state), None);
+        }
+        /* Digits wrap around */
+        menu.turn(&state, -6);
+        assert_eq!(menu.click(&state), Some(Command::Lock(1234)));
+        assert_eq!(at(&menu), ("System", 1));
+
+        state.lock(1234);
+        menu.click(&state);
+        for _ in 0..CODE_DIGITS {
+            menu.click(&state);
+        }
+        assert!(menu.position == MenuPosition::List);
+        assert!(state.lock.is_locked());
+    }
+
+    #[test]
+    fn emergency_stop_takes_over() {
+        let mut state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        state.emergency_stop();
+        assert_eq!(menu.turn(&state, 1), None);
+        assert_eq!(at(&menu), ("Home", 1));
+        assert_eq!(menu.click(&state), Some(Command::AcknowledgeStop));
+        assert!(menu.position == MenuPosition::Main && menu.level().is_none());
+    }
+
+    #[test]
+    fn click_flips_toggle_in_place() {
+        let mut state = State::new();
+        let mut menu = Menu::default();
+        menu.turn(&state, 1);
+        menu.turn(&state, 3);
+        menu.click(&state);
+        menu.turn(&state, 3);
+        let command = menu.click(&state);
+        assert_eq!(command, Some(Command::Cycle(Setting::AutoStart)));
+        assert!(menu.position == MenuPosition::List);
+
+        dispatch(&mut state, command.unwrap());
+        assert!(state.auto_start);
+        let command = menu.click(&state).unwrap();
+        dispatch(&mut state, command);
+        assert!(!state.auto_start);
     }
 }
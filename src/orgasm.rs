@@ -0,0 +1,214 @@
+use crate::state::State;
+use heapless::Deque;
+use log::debug;
+
+pub const MAX_CONTRACTIONS: usize = 8;
+const HOLDOFF_MS: u32 = 30_000;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum OrgasmAction {
+    Log,
+    EndSession,
+    Ruin,
+}
+
+impl OrgasmAction {
+    pub fn from_index(index: u32) -> OrgasmAction {
+        use OrgasmAction::*;
+        match index {
+            1 => EndSession,
+            2 => Ruin,
+            _ => Log,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use OrgasmAction::*;
+        match self {
+            Log => "log only",
+            EndSession => "end session",
+            Ruin => "ruin",
+        }
+    }
+}
+
+/* Recognises the rhythmic, high amplitude contraction train of an orgasm */
+pub struct OrgasmDetector {
+    above: bool,
+    contractions: Deque<u32, MAX_CONTRACTIONS>,
+    holdoff_until: u32,
+}
+
+impl OrgasmDetector {
+    pub fn new() -> OrgasmDetector {
+        OrgasmDetector {
+            above: false,
+            contractions: Deque::new(),
+            holdoff_until: 0,
+        }
+    }
+
+    pub fn add(&mut self, cur: u32, time: u32, state: &State) -> bool {
+        /* Count a contraction on every rising edge, with hysteresis on the way down */
+        if !self.above && cur >= state.orgasm_peak_thresh {
+            self.above = true;
+            if let Some(&last) = self.contractions.back() {
+                if time.wrapping_sub(last) > state.orgasm_max_interval {
+                    self.contractions.clear();
+                }
+            }
+            if self.contractions.is_full() {
+                self.contractions.pop_front();
+            }
+            self.contractions.push_back(time).ok();
+            debug!("Contraction, {} in train", self.contractions.len());
+        } else if self.above && cur < state.orgasm_peak_thresh / 2 {
+            self.above = false;
+        }
+
+        if time < self.holdoff_until || !self.is_rhythmic(state) {
+            return false;
+        }
+        self.contractions.clear();
+        self.holdoff_until = time + HOLDOFF_MS;
+        true
+    }
+
+    fn is_rhythmic(&self, state: &State) -> bool {
+        let count = state.orgasm_contractions as usize;
+        if count < 2 || self.contractions.len() < count {
+            return false;
+        }
+        let mut min = u32::MAX;
+        let mut max = 0;
+        let mut last = None;
+        for &time in self
+            .contractions
+            .iter()
+            .skip(self.contractions.len() - count)
+        {
+            if let Some(last) = last {
+                let interval = time.wrapping_sub(last);
+                min = min.min(interval);
+                max = max.max(interval);
+            }
+            last = Some(time);
+        }
+        min >= state.orgasm_min_interval && max <= state.orgasm_max_interval && max <= min * 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Samples every 25ms of contractions 150ms long, starting at `start` and then `gaps`
+     * apart. Returns when the detector recognised an orgasm */
+    fn train(
+        detector: &mut OrgasmDetector,
+        state: &State,
+        start: u32,
+        gaps: &[u32],
+    ) -> Option<u32> {
+        let mut contractions = heapless::Vec::<u32, 16>::new();
+        contractions.push(start).unwrap();
+        for gap in gaps {
+            let last = *contractions.last().unwrap();
+            contractions.push(last + gap).unwrap();
+        }
+        let end = contractions.last().unwrap() + 1_000;
+        let mut found = None;
+        for time in (start..end).step_by(25) {
+            let contracting = contractions
+                .iter()
+                .any(|&at| (at..at + 150).contains(&time));
+            let cur = match contracting {
+                true => state.orgasm_peak_thresh * 2,
+                false => state.orgasm_peak_thresh / 4,
+            };
+            if detector.add(cur, time, state) && found.is_none() {
+                found = Some(time);
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn recognises_contraction_train() {
+        let state = State::new();
+        let mut detector = OrgasmDetector::new();
+        let found = train(&mut detector, &state, 1_000, &[800, 800, 800]);
+        /* On the last of the default 4 contractions */
+        assert_eq!(found, Some(1_000 + 3 * 800));
+
+        let mut detector = OrgasmDetector::new();
+        assert!(train(&mut detector, &state, 1_000, &[700, 900, 1_000, 800]).is_some());
+    }
+
+    #[test]
+    fn near_misses_do_not_trigger() {
+        let state = State::new();
+        for gaps in [
+            /* One contraction short */
+            &[800, 800][..],
+            /* Too fast to be contractions */
+            &[300, 300, 300],
+            /* Too slow, each one starts a new train */
+            &[1_600, 1_600, 1_600],
+            /* Not rhythmic */
+            &[450, 1_400, 450],
+        ] {
+            let mut detector = OrgasmDetector::new();
+            assert_eq!(
+                train(&mut detector, &state, 1_000, gaps),
+                None,
+                "{:?}",
+                gaps
+            );
+        }
+    }
+
+    #[test]
+    fn holds_off_after_orgasm() {
+        let state = State::new();
+        let mut detector = OrgasmDetector::new();
+        let first = train(&mut detector, &state, 1_000, &[800, 800, 800]).unwrap();
+        /* The rest of the same orgasm is not a second one */
+        assert_eq!(train(&mut detector, &state, 4_200, &[800; 6]), None);
+        assert_eq!(
+            train(&mut detector, &state, first + 10_000, &[800, 800, 800]),
+            None
+        );
+        assert!(train(&mut detector, &state, first + HOLDOFF_MS, &[800, 800, 800]).is_some());
+    }
+
+    fn orgasm(action: OrgasmAction) -> State {
+        let mut state = State::new();
+        state.orgasm_action = action;
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        state.cur_time_ms = 60_000;
+        state.orgasm(60_000);
+        state
+    }
+
+    #[test]
+    fn orgasm_actions() {
+        let state = orgasm(OrgasmAction::Log);
+        assert!(state.running && state.stimulating);
+        assert_eq!(state.stats.orgasms, 1);
+
+        let state = orgasm(OrgasmAction::EndSession);
+        assert!(!state.running);
+        assert_eq!(state.get_cur_intensity(), 0);
+
+        let mut state = orgasm(OrgasmAction::Ruin);
+        assert!(state.running && !state.stimulating);
+        assert_eq!(state.hysteresis.remaining(60_000), state.ruin_time);
+        assert_eq!(state.get_cur_intensity(), 0);
+        let last = state.log.newer_than(0).last().unwrap();
+        assert_eq!(last.kind.name(), "cooldown_start");
+        state.cur_time_ms = 60_000 + state.ruin_time;
+        assert!(!state.hysteresis.is_active(state.cur_time_ms));
+    }
+}
@@ -0,0 +1,85 @@
+/* The OLED panels the display can be built for, picked with cargo features */
+use hal::i2c::I2C;
+use hal::peripherals::I2C0;
+
+use crate::display::Panel;
+
+#[cfg(not(feature = "sh1106"))]
+use display_interface::WriteOnlyDataCommand;
+#[cfg(not(feature = "sh1106"))]
+use ssd1306::{mode::BufferedGraphicsMode, prelude::*, I2CDisplayInterface, Ssd1306};
+
+#[cfg(not(feature = "sh1106"))]
+impl<DI, SIZE> Panel for Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>
+where
+    DI: WriteOnlyDataCommand,
+    SIZE: DisplaySize,
+{
+    fn clear_buffer(&mut self) {
+        Ssd1306::clear_buffer(self);
+    }
+
+    fn flush(&mut self) {
+        Ssd1306::flush(self).unwrap();
+    }
+}
+
+#[cfg(feature = "sh1106")]
+impl<DI> Panel for sh1106::mode::GraphicsMode<DI>
+where
+    DI: sh1106::interface::DisplayInterface,
+{
+    fn clear_buffer(&mut self) {
+        self.clear();
+    }
+
+    fn flush(&mut self) {
+        sh1106::mode::GraphicsMode::flush(self).unwrap();
+    }
+}
+
+#[cfg(all(not(feature = "sh1106"), not(feature = "oled-128x64")))]
+pub type OLEDPanel<'a> = Ssd1306<
+    I2CInterface<I2C<'a, I2C0>>,
+    DisplaySize128x32,
+    BufferedGraphicsMode<DisplaySize128x32>,
+>;
+
+#[cfg(all(not(feature = "sh1106"), feature = "oled-128x64"))]
+pub type OLEDPanel<'a> = Ssd1306<
+    I2CInterface<I2C<'a, I2C0>>,
+    DisplaySize128x64,
+    BufferedGraphicsMode<DisplaySize128x64>,
+>;
+
+#[cfg(feature = "sh1106")]
+pub type OLEDPanel<'a> = sh1106::mode::GraphicsMode<sh1106::interface::I2cInterface<I2C<'a, I2C0>>>;
+
+#[cfg(not(feature = "sh1106"))]
+pub fn new(i2c: I2C<'_, I2C0>) -> OLEDPanel<'_> {
+    #[cfg(not(feature = "oled-128x64"))]
+    let size = DisplaySize128x32;
+    #[cfg(feature = "oled-128x64")]
+    let size = DisplaySize128x64;
+
+    let mut panel = Ssd1306::new(
+        I2CDisplayInterface::new(i2c),
+        size,
+        DisplayRotation::Rotate0,
+    )
+    .into_buffered_graphics_mode();
+    panel.init().unwrap();
+    panel
+}
+
+#[cfg(feature = "sh1106")]
+pub fn new(i2c: I2C<'_, I2C0>) -> OLEDPanel<'_> {
+    use sh1106::{displaysize::DisplaySize, Builder};
+
+    let mut panel: OLEDPanel = Builder::new()
+        .with_size(DisplaySize::Display128x64)
+        .connect_i2c(i2c)
+        .into();
+    panel.init().unwrap();
+    panel
+}
@@ -0,0 +1,349 @@
+use crate::cooldown::CooldownPolicy;
+use crate::orgasm::{OrgasmAction, MAX_CONTRACTIONS};
+use crate::profile::PROFILES;
+use crate::program::ProgramKind;
+use crate::ramp::RampCurve;
+use crate::state::{State, MAX_INTENSITY};
+
+/* Identifies a parameter by its index into PARAMS, which is also how it is logged and stored */
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum Setting {
+    Sensitivity,
+    Density,
+    Duration,
+    Cooldown,
+    CooldownPolicy,
+    RampTime,
+    RampCurve,
+    Grace,
+    Intensity,
+    AutoStart,
+    Tease,
+    AutoRamp,
+    Profile,
+    Program,
+    OrgasmAction,
+    RhythmWarning,
+    LogToFlash,
+    Telemetry,
+    EncoderInvert,
+    EncoderAcceleration,
+    LockTime,
+    MaxSession,
+    MaxStimulation,
+    BreakAfter,
+    BreakLength,
+    IntensityCeiling,
+    InsertionThreshold,
+    InsertionSettle,
+    RemovalTime,
+    TeaseCooldownMin,
+    TeaseCooldownMax,
+    TeaseIntensityMin,
+    TeaseIntensityMax,
+    TeaseCutChance,
+    AutoRampRate,
+    AutoRampFloor,
+    AutoRampCeiling,
+    AutoRampBackoff,
+    ProgramEdgeGoal,
+    ProgramDuration,
+    CooldownIncrement,
+    CooldownFactor,
+    CooldownMax,
+    CooldownReset,
+    OrgasmThreshold,
+    OrgasmContractions,
+    OrgasmMinInterval,
+    OrgasmMaxInterval,
+    RuinTime,
+    RhythmThreshold,
+}
+
+#[derive(Copy, Clone)]
+pub enum ParamKind {
+    /* A number, stepped with the encoder on its own edit screen */
+    Value,
+    /* On or off, flipped by clicking its row in the menu */
+    Toggle,
+    /* One of min..=max, picked with the encoder on its own edit screen, with the name of
+     * each option */
+    Choice(fn(u32) -> &'static str),
+}
+
+pub struct Param {
+    pub setting: Setting,
+    /* Used on the console and in the log */
+    pub name: &'static str,
+    /* Shown on the display */
+    pub label: &'static str,
+    pub unit: &'static str,
+    pub min: u32,
+    pub max: u32,
+    pub step: u32,
+    pub default: u32,
+    /* The display shows value / scale */
+    pub scale: u32,
+    pub kind: ParamKind,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn value(
+    setting: Setting,
+    name: &'static str,
+    label: &'static str,
+    unit: &'static str,
+    min: u32,
+    max: u32,
+    step: u32,
+    default: u32,
+    scale: u32,
+) -> Param {
+    Param {
+        setting,
+        name,
+        label,
+        unit,
+        min,
+        max,
+        step,
+        default,
+        scale,
+        kind: ParamKind::Value,
+    }
+}
+
+const fn toggle(setting: Setting, name: &'static str, label: &'static str, default: bool) -> Param {
+    Param {
+        setting,
+        name,
+        label,
+        unit: "",
+        min: 0,
+        max: 1,
+        step: 1,
+        default: default as u32,
+        scale: 1,
+        kind: ParamKind::Toggle,
+    }
+}
+
+const fn choice(
+    setting: Setting,
+    name: &'static str,
+    label: &'static str,
+    options: usize,
+    option_name: fn(u32) -> &'static str,
+) -> Param {
+    Param {
+        setting,
+        name,
+        label,
+        unit: "",
+        min: 0,
+        max: options as u32 - 1,
+        step: 1,
+        default: 0,
+        scale: 1,
+        kind: ParamKind::Choice(option_name),
+    }
+}
+
+/* Every tunable. Entries must stay in the order of Setting, new ones go at the end so
+ * stored settings keep their index */
+#[rustfmt::skip]
+pub const PARAMS: [Param; 50] = [
+    value(Setting::Sensitivity, "sensitivity", "Sensitivity", "", 0, 1_000_000, 1_000, 15_000, 1),
+    value(Setting::Density, "density", "Density", "", 0, 10_000_000, 10_000, 200_000, 1),
+    value(Setting::Duration, "duration", "Duration", "ms", 0, 5_000, 25, 500, 1),
+    value(Setting::Cooldown, "cooldown", "Cooldown time", "s", 0, 600_000, 1_000, 10_000, 1_000),
+    choice(Setting::CooldownPolicy, "cooldown_policy", "Cooldown mode", 3, |i| CooldownPolicy::from_index(i).name()),
+    value(Setting::RampTime, "ramp_time", "Resume ramp", "ms", 0, 30_000, 500, 5_000, 1),
+    choice(Setting::RampCurve, "ramp_curve", "Ramp curve", 3, |i| RampCurve::from_index(i).name()),
+    value(Setting::Grace, "grace", "Resume grace", "ms", 0, 10_000, 500, 3_000, 1),
+    value(Setting::Intensity, "intensity", "Intensity", "/20", 0, MAX_INTENSITY as u32, 1, 10, 1),
+    toggle(Setting::AutoStart, "auto_start", "Auto start", false),
+    toggle(Setting::Tease, "tease", "Tease mode", false),
+    toggle(Setting::AutoRamp, "auto_ramp", "Auto ramp", false),
+    choice(Setting::Profile, "profile", "Profile", PROFILES.len(), |i| PROFILES[i as usize].name),
+    choice(Setting::Program, "program", "Program", 4, |i| ProgramKind::from_index(i).name()),
+    choice(Setting::OrgasmAction, "orgasm_action", "On orgasm", 3, |i| OrgasmAction::from_index(i).name()),
+    toggle(Setting::RhythmWarning, "rhythm_warning", "Rhythm warning", false),
+    toggle(Setting::LogToFlash, "log_to_flash", "Save log", false),
+    value(Setting::Telemetry, "telemetry", "Telemetry", "ms", 0, 1_000, 25, 0, 1),
+    toggle(Setting::EncoderInvert, "encoder_invert", "Invert knob", false),
+    toggle(Setting::EncoderAcceleration, "encoder_accel", "Knob accel", true),
+    value(Setting::LockTime, "lock_time", "Lock timer", "min", 0, 86_400_000, 900_000, 0, 60_000),
+    value(Setting::MaxSession, "max_session", "Max session", "min", 0, 36_000_000, 300_000, 0, 60_000),
+    value(Setting::MaxStimulation, "max_stim", "Max stim", "min", 0, 7_200_000, 60_000, 0, 60_000),
+    value(Setting::BreakAfter, "break_after", "Break after", "min", 0, 14_400_000, 300_000, 0, 60_000),
+    value(Setting::BreakLength, "break_length", "Break length", "min", 60_000, 3_600_000, 60_000, 300_000, 60_000),
+    value(Setting::IntensityCeiling, "ceiling", "Max intensity", "/20", 0, MAX_INTENSITY as u32, 1, MAX_INTENSITY as u32, 1),
+    value(Setting::InsertionThreshold, "insertion_thresh", "Insert level", "", 0, 1_000_000, 1_000, 30_000, 1),
+    value(Setting::InsertionSettle, "insertion_settle", "Insert settle", "ms", 0, 30_000, 500, 5_000, 1),
+    value(Setting::RemovalTime, "removal_time", "Removal time", "ms", 0, 30_000, 500, 2_000, 1),
+    value(Setting::TeaseCooldownMin, "tease_off_min", "Tease off min", "s", 0, 600_000, 1_000, 5_000, 1_000),
+    value(Setting::TeaseCooldownMax, "tease_off_max", "Tease off max", "s", 0, 600_000, 1_000, 30_000, 1_000),
+    value(Setting::TeaseIntensityMin, "tease_int_min", "Tease min", "/20", 0, MAX_INTENSITY as u32, 1, 5, 1),
+    value(Setting::TeaseIntensityMax, "tease_int_max", "Tease max", "/20", 0, MAX_INTENSITY as u32, 1, MAX_INTENSITY as u32, 1),
+    value(Setting::TeaseCutChance, "tease_cut_chance", "Tease cuts", "/1000", 0, 1_000, 5, 20, 1),
+    value(Setting::AutoRampRate, "auto_ramp_rate", "Ramp rate", "/min", 1, 60, 1, 2, 1),
+    value(Setting::AutoRampFloor, "auto_ramp_floor", "Ramp floor", "/20", 0, MAX_INTENSITY as u32, 1, 5, 1),
+    value(Setting::AutoRampCeiling, "auto_ramp_ceiling", "Ramp ceiling", "/20", 0, MAX_INTENSITY as u32, 1, MAX_INTENSITY as u32, 1),
+    value(Setting::AutoRampBackoff, "auto_ramp_backoff", "Ramp backoff", "/20", 0, MAX_INTENSITY as u32, 1, 3, 1),
+    value(Setting::ProgramEdgeGoal, "program_edges", "Edge goal", "", 1, 100, 1, 5, 1),
+    value(Setting::ProgramDuration, "program_duration", "Program time", "min", 60_000, 14_400_000, 60_000, 1_200_000, 60_000),
+    value(Setting::CooldownIncrement, "cooldown_step", "Cooldown step", "s", 0, 600_000, 1_000, 5_000, 1_000),
+    value(Setting::CooldownFactor, "cooldown_factor", "Cooldown x", "%", 100, 400, 10, 150, 1),
+    value(Setting::CooldownMax, "cooldown_max", "Cooldown max", "s", 0, 3_600_000, 5_000, 60_000, 1_000),
+    value(Setting::CooldownReset, "cooldown_reset", "Calm reset", "s", 0, 3_600_000, 10_000, 120_000, 1_000),
+    value(Setting::OrgasmThreshold, "orgasm_thresh", "Orgasm level", "", 0, 1_000_000, 1_000, 30_000, 1),
+    value(Setting::OrgasmContractions, "orgasm_count", "Contractions", "", 2, MAX_CONTRACTIONS as u32, 1, 4, 1),
+    value(Setting::OrgasmMinInterval, "orgasm_min_gap", "Min gap", "ms", 100, 5_000, 50, 400, 1),
+    value(Setting::OrgasmMaxInterval, "orgasm_max_gap", "Max gap", "ms", 100, 5_000, 50, 1_500, 1),
+    value(Setting::RuinTime, "ruin_time", "Ruin time", "s", 0, 600_000, 1_000, 30_000, 1_000),
+    value(Setting::RhythmThreshold, "rhythm_thresh", "Rhythm level", "/1000", 0, 1_000, 10, 700, 1),
+];
+
+impl Setting {
+    pub fn param(self) -> &'static Param {
+        &PARAMS[self as usize]
+    }
+
+    pub fn name(self) -> &'static str {
+        self.param().name
+    }
+
+    pub fn from_index(index: u32) -> Option<Setting> {
+        PARAMS.get(index as usize).map(|param| param.setting)
+    }
+
+    /* Safety limits are stored on their own, see limits.rs */
+    pub fn is_limit(self) -> bool {
+        use Setting::*;
+        matches!(
+            self,
+            MaxSession | MaxStimulation | BreakAfter | BreakLength | IntensityCeiling
+        )
+    }
+
+    pub fn from_name(name: &str) -> Option<Setting> {
+        PARAMS
+            .iter()
+            .find(|param| param.name == name)
+            .map(|param| param.setting)
+    }
+}
+
+impl Param {
+    pub fn clamp(&self, value: u32) -> u32 {
+        value.clamp(self.min, self.max)
+    }
+
+    /* Value after a number of encoder steps, within the limits. Toggles and choices wrap around */
+    pub fn stepped(&self, value: u32, steps: i32) -> u32 {
+        if !matches!(self.kind, ParamKind::Value) {
+            let options = (self.max - self.min + 1) as i64;
+            let offset = (value as i64 - self.min as i64 + steps as i64).rem_euclid(options);
+            return self.min + offset as u32;
+        }
+        let delta = self.step.saturating_mul(steps.unsigned_abs());
+        match steps < 0 {
+            true => self.clamp(value.saturating_sub(delta)),
+            false => self.clamp(value.saturating_add(delta)),
+        }
+    }
+
+    /* What a click does to a toggle or choice */
+    pub fn cycled(&self, value: u32) -> u32 {
+        match value >= self.max {
+            true => self.min,
+            false => value + 1,
+        }
+    }
+}
+
+/* Settings are stored as (index, value) records, so parameters can be added without losing the others */
+pub const RECORD_SIZE: usize = 5;
+pub const SERIALIZED_SIZE: usize = PARAMS.len() * RECORD_SIZE;
+
+pub fn serialize(state: &State, buf: &mut [u8; SERIALIZED_SIZE]) -> usize {
+    let params = PARAMS.iter().filter(|param| !param.setting.is_limit());
+    let mut len = 0;
+    for (param, record) in params.zip(buf.chunks_exact_mut(RECORD_SIZE)) {
+        record[0] = param.setting as u8;
+        record[1..5].copy_from_slice(&state.get_setting(param.setting).to_le_bytes());
+        len += RECORD_SIZE;
+    }
+    len
+}
+
+pub fn deserialize(state: &mut State, buf: &[u8]) {
+    for record in buf.chunks_exact(RECORD_SIZE) {
+        let setting = Setting::from_index(record[0] as u32).filter(|setting| !setting.is_limit());
+        if let Some(setting) = setting {
+            let value = u32::from_le_bytes(record[1..5].try_into().unwrap());
+            state.restore_setting(setting, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_follow_setting_order() {
+        for (i, param) in PARAMS.iter().enumerate() {
+            assert_eq!(param.setting as usize, i, "{} is out of order", param.name);
+            assert_eq!(Setting::from_index(i as u32), Some(param.setting));
+        }
+    }
+
+    #[test]
+    fn params_are_consistent() {
+        for (i, param) in PARAMS.iter().enumerate() {
+            assert!(
+                (param.min..=param.max).contains(&param.default),
+                "{}",
+                param.name
+            );
+            assert!(param.step > 0 && param.scale > 0, "{}", param.name);
+            assert!(
+                PARAMS[..i].iter().all(|other| other.name != param.name),
+                "{}",
+                param.name
+            );
+        }
+    }
+
+    #[test]
+    fn state_starts_at_defaults() {
+        let state = State::new();
+        for param in PARAMS.iter() {
+            assert_eq!(
+                state.get_setting(param.setting),
+                param.default,
+                "{}",
+                param.name
+            );
+        }
+    }
+
+    #[test]
+    fn settings_roundtrip() {
+        let mut state = State::new();
+        state.set_setting(Setting::Grace, 1_234);
+        state.set_setting(Setting::CooldownFactor, 200);
+        state.set_setting(Setting::Program, 2);
+        let mut buf = [0u8; SERIALIZED_SIZE];
+        let len = serialize(&state, &mut buf);
+
+        let mut restored = State::new();
+        deserialize(&mut restored, &buf[..len]);
+        assert_eq!(restored.resume_grace_time, 1_234);
+        assert_eq!(restored.cooldown_factor, 200);
+        assert_eq!(restored.get_setting(Setting::Program), 2);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::rng::Rng;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Pattern {
+    Constant,
+    Sine,
+    Square,
+    Sawtooth,
+    Flutter,
+}
+
+impl Pattern {
+    pub fn next(self) -> Pattern {
+        use Pattern::*;
+        match self {
+            Constant => Sine,
+            Sine => Square,
+            Square => Sawtooth,
+            Sawtooth => Flutter,
+            Flutter => Constant,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use Pattern::*;
+        match self {
+            Constant => "constant",
+            Sine => "waves",
+            Square => "pulses",
+            Sawtooth => "build",
+            Flutter => "flutter",
+        }
+    }
+
+    /* Wave level in per mille at the given time, a pure function of time */
+    fn wave(self, time: u32, period: u32) -> u32 {
+        use Pattern::*;
+        if period == 0 {
+            return 1000;
+        }
+        let phase = (time % period) as u64 * 1000 / period as u64;
+        let phase = phase as u32;
+        match self {
+            Constant => 1000,
+            Sine => {
+                /* Bhaskara's approximation of sin over each half period */
+                let x = phase % 500;
+                let sin = 16_000 * x * (500 - x) / (1_250_000 - 4 * x * (500 - x));
+                if phase < 500 {
+                    500 + sin / 2
+                } else {
+                    500 - sin / 2
+                }
+            }
+            Square if phase < 500 => 1000,
+            Square => 0,
+            Sawtooth => phase,
+            Flutter => {
+                let slot = time / period;
+                Rng::new(slot.wrapping_mul(0x9E37_79B9) ^ 0x5bd1_e995).range(0, 1000)
+            }
+        }
+    }
+
+    /* Shapes the base intensity, `depth` is how far (in percent) the pattern pulls it down */
+    pub fn apply(self, base: u8, time: u32, period: u32, depth: u32) -> u8 {
+        let depth = depth.min(100) * 10;
+        let level = 1000 - depth * (1000 - self.wave(time, period)) / 1000;
+        (base as u32 * level / 1000) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: u32 = 1_000;
+
+    #[test]
+    fn constant_keeps_base() {
+        for time in (0..3_000).step_by(50) {
+            assert_eq!(Pattern::Constant.apply(12, time, PERIOD, 100), 12);
+        }
+    }
+
+    #[test]
+    fn sine_shape() {
+        let sine = |time| Pattern::Sine.wave(time, PERIOD);
+        assert_eq!(sine(0), 500);
+        assert!(sine(250) >= 995);
+        assert_eq!(sine(500), 500);
+        assert!(sine(750) <= 5);
+        assert!(sine(100) < sine(200) && sine(600) > sine(700));
+    }
+
+    #[test]
+    fn square_shape() {
+        assert_eq!(Pattern::Square.wave(0, PERIOD), 1000);
+        assert_eq!(Pattern::Square.wave(499, PERIOD), 1000);
+        assert_eq!(Pattern::Square.wave(500, PERIOD), 0);
+        assert_eq!(Pattern::Square.wave(999, PERIOD), 0);
+    }
+
+    #[test]
+    fn sawtooth_shape() {
+        assert_eq!(Pattern::Sawtooth.wave(0, PERIOD), 0);
+        assert_eq!(Pattern::Sawtooth.wave(250, PERIOD), 250);
+        assert_eq!(Pattern::Sawtooth.wave(999, PERIOD), 999);
+    }
+
+    #[test]
+    fn flutter_holds_level_for_a_period() {
+        let level = Pattern::Flutter.wave(1_000, PERIOD);
+        assert!(level <= 1000);
+        assert_eq!(Pattern::Flutter.wave(1_999, PERIOD), level);
+        let levels: heapless::Vec<u32, 8> = (0..8)
+            .map(|slot| Pattern::Flutter.wave(slot * PERIOD, PERIOD))
+            .collect();
+        assert!(levels.iter().any(|&other| other != level));
+    }
+
+    #[test]
+    fn patterns_repeat_every_period() {
+        /* Flutter draws a new level every period instead */
+        let mut pattern = Pattern::Constant;
+        while pattern != Pattern::Flutter {
+            for time in (0..PERIOD).step_by(37) {
+                assert_eq!(
+                    pattern.apply(20, time, PERIOD, 60),
+                    pattern.apply(20, time + 3 * PERIOD, PERIOD, 60)
+                );
+            }
+            pattern = pattern.next();
+        }
+    }
+
+    #[test]
+    fn depth_limits_how_far_the_output_drops() {
+        assert_eq!(Pattern::Square.apply(20, 750, PERIOD, 0), 20);
+        assert_eq!(Pattern::Square.apply(20, 750, PERIOD, 50), 10);
+        assert_eq!(Pattern::Square.apply(20, 750, PERIOD, 100), 0);
+        assert_eq!(Pattern::Square.apply(20, 250, PERIOD, 100), 20);
+        /* Without a period the output is not shaped */
+        assert_eq!(Pattern::Square.apply(20, 750, 0, 100), 20);
+    }
+}
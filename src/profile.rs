@@ -0,0 +1,41 @@
+use crate::pattern::Pattern;
+
+pub struct Profile {
+    pub name: &'static str,
+    pub pattern: Pattern,
+    pub pattern_period: u32,
+    pub pattern_depth: u32,
+}
+
+pub const PROFILES: [Profile; 5] = [
+    Profile {
+        name: "Steady",
+        pattern: Pattern::Constant,
+        pattern_period: 0,
+        pattern_depth: 0,
+    },
+    Profile {
+        name: "Waves",
+        pattern: Pattern::Sine,
+        pattern_period: 8_000,
+        pattern_depth: 60,
+    },
+    Profile {
+        name: "Pulses",
+        pattern: Pattern::Square,
+        pattern_period: 2_000,
+        pattern_depth: 80,
+    },
+    Profile {
+        name: "Build",
+        pattern: Pattern::Sawtooth,
+        pattern_period: 20_000,
+        pattern_depth: 70,
+    },
+    Profile {
+        name: "Flutter",
+        pattern: Pattern::Flutter,
+        pattern_period: 300,
+        pattern_depth: 50,
+    },
+];
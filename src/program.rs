@@ -0,0 +1,244 @@
+use log::info;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ProgramKind {
+    Free,
+    EdgeGoal,
+    Timed,
+    DenyThenRuin,
+}
+
+impl ProgramKind {
+    pub fn from_index(index: u32) -> ProgramKind {
+        use ProgramKind::*;
+        match index {
+            1 => EdgeGoal,
+            2 => Timed,
+            3 => DenyThenRuin,
+            _ => Free,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use ProgramKind::*;
+        match self {
+            Free => "free",
+            EdgeGoal => "edge goal",
+            Timed => "timed",
+            DenyThenRuin => "deny, ruin",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ProgramState {
+    Idle,
+    Edging,
+    /* Goal reached, detection is disabled */
+    Finish,
+    /* Deny timer expired, the next edge ends the session */
+    Ruin,
+    Done,
+}
+
+pub enum ProgramResult {
+    Continue,
+    End,
+}
+
+pub struct Program {
+    pub state: ProgramState,
+    pub edges: u32,
+    pub start_time: u32,
+}
+
+impl Program {
+    pub fn new() -> Program {
+        Program {
+            state: ProgramState::Idle,
+            edges: 0,
+            start_time: 0,
+        }
+    }
+
+    pub fn start(&mut self, time: u32, kind: ProgramKind) {
+        self.state = match kind {
+            ProgramKind::Free => ProgramState::Idle,
+            _ => ProgramState::Edging,
+        };
+        self.edges = 0;
+        self.start_time = time;
+    }
+
+    pub fn stop(&mut self) {
+        self.state = ProgramState::Idle;
+    }
+
+    pub fn detection_enabled(&self) -> bool {
+        self.state != ProgramState::Finish
+    }
+
+    pub fn remaining(&self, time: u32, duration: u32) -> u32 {
+        duration.saturating_sub(time.wrapping_sub(self.start_time))
+    }
+
+    pub fn edge(&mut self, kind: ProgramKind, edge_goal: u32) -> ProgramResult {
+        use ProgramState::*;
+        self.edges += 1;
+        match self.state {
+            Edging if kind == ProgramKind::EdgeGoal && self.edges >= edge_goal => {
+                info!("Program: edge goal reached, finishing allowed");
+                self.state = Finish;
+                ProgramResult::Continue
+            }
+            Ruin => {
+                info!("Program: ruined");
+                self.state = Done;
+                ProgramResult::End
+            }
+            _ => ProgramResult::Continue,
+        }
+    }
+
+    pub fn orgasm(&mut self) -> ProgramResult {
+        if self.state != ProgramState::Finish {
+            return ProgramResult::Continue;
+        }
+        info!("Program: finished");
+        self.state = ProgramState::Done;
+        ProgramResult::End
+    }
+
+    pub fn update(&mut self, time: u32, kind: ProgramKind, duration: u32) -> ProgramResult {
+        use ProgramState::*;
+        if self.state != Edging || self.remaining(time, duration) > 0 {
+            return ProgramResult::Continue;
+        }
+        match kind {
+            ProgramKind::Timed => {
+                info!("Program: time is up");
+                self.state = Done;
+                ProgramResult::End
+            }
+            ProgramKind::DenyThenRuin => {
+                info!("Program: denial over, ruining the next edge");
+                self.state = Ruin;
+                ProgramResult::Continue
+            }
+            _ => ProgramResult::Continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+
+    fn session(kind: ProgramKind) -> State {
+        let mut state = State::new();
+        state.program_kind = kind;
+        state.program_edge_goal = 3;
+        state.program_duration = 60_000;
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        state
+    }
+
+    /* An edge as the detector reports it, once the previous cooldown is over */
+    fn edge(state: &mut State, time: u32) {
+        state.cur_time_ms = time;
+        state.update();
+        state.start_stim();
+        state.edge(time, 300_000, 5_000);
+        if state.running {
+            state.stop_stim();
+        }
+    }
+
+    #[test]
+    fn edge_goal_allows_finishing() {
+        let mut state = session(ProgramKind::EdgeGoal);
+        edge(&mut state, 100_000);
+        edge(&mut state, 200_000);
+        assert!(state.program.state == ProgramState::Edging);
+        assert!(state.detection_enabled());
+
+        edge(&mut state, 300_000);
+        assert!(state.program.state == ProgramState::Finish);
+        assert!(!state.detection_enabled() && state.running);
+
+        /* More edges do not end it, only an orgasm does */
+        edge(&mut state, 400_000);
+        assert!(state.program.state == ProgramState::Finish);
+        state.start_stim();
+        state.orgasm(400_500);
+        assert!(state.program.state == ProgramState::Done);
+        assert!(!state.running);
+        assert_eq!(state.get_cur_intensity(), 0);
+    }
+
+    #[test]
+    fn one_long_peak_is_one_edge_towards_goal() {
+        let mut state = session(ProgramKind::EdgeGoal);
+        let mut history = crate::history::Nogasm::<4>::new();
+        history.add(1_000_000, 1_000, &mut state);
+        let high = 1_000_000 + 4 * state.peak_value_thresh;
+        for time in (1_025..30_000).step_by(25) {
+            history.add(high, time, &mut state);
+        }
+        assert_eq!(state.program.edges, 1);
+        assert!(state.program.state == ProgramState::Edging);
+    }
+
+    #[test]
+    fn orgasm_before_goal_does_not_finish() {
+        let mut program = Program::new();
+        program.start(0, ProgramKind::EdgeGoal);
+        assert!(matches!(program.orgasm(), ProgramResult::Continue));
+        assert!(program.state == ProgramState::Edging);
+    }
+
+    #[test]
+    fn timed_ends_when_time_is_up() {
+        let mut state = session(ProgramKind::Timed);
+        edge(&mut state, 30_000);
+        state.start_stim();
+        state.cur_time_ms = 60_999;
+        state.update();
+        assert!(state.running && state.get_cur_intensity() > 0);
+
+        state.cur_time_ms = 61_000;
+        state.update();
+        assert!(state.program.state == ProgramState::Done);
+        assert!(!state.running);
+        assert_eq!(state.get_cur_intensity(), 0);
+    }
+
+    #[test]
+    fn deny_then_ruin_ends_on_first_edge_after_timer() {
+        let mut state = session(ProgramKind::DenyThenRuin);
+        edge(&mut state, 30_000);
+        state.cur_time_ms = 61_000;
+        state.update();
+        assert!(state.program.state == ProgramState::Ruin);
+        /* Detection carries on, the session keeps going until the edge */
+        assert!(state.running && state.detection_enabled());
+
+        edge(&mut state, 90_000);
+        assert!(state.program.state == ProgramState::Done);
+        assert!(!state.running);
+        assert_eq!(state.get_cur_intensity(), 0);
+    }
+
+    #[test]
+    fn free_session_has_no_program() {
+        let mut state = session(ProgramKind::Free);
+        for time in (100_000..1_000_000).step_by(100_000) {
+            edge(&mut state, time);
+        }
+        state.cur_time_ms = 2_000_000;
+        state.update();
+        assert!(state.program.state == ProgramState::Idle && state.running);
+    }
+}
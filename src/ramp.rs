@@ -0,0 +1,37 @@
+#[derive(Copy, Clone, PartialEq)]
+pub enum RampCurve {
+    Linear,
+    EaseIn,
+    EaseOut,
+}
+
+impl RampCurve {
+    pub fn from_index(index: u32) -> RampCurve {
+        use RampCurve::*;
+        match index {
+            1 => EaseIn,
+            2 => EaseOut,
+            _ => Linear,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        use RampCurve::*;
+        match self {
+            Linear => "linear",
+            EaseIn => "ease in",
+            EaseOut => "ease out",
+        }
+    }
+
+    /* Maps progress in per mille to output level in per mille */
+    pub fn apply(self, progress: u32) -> u32 {
+        use RampCurve::*;
+        let progress = progress.min(1000);
+        match self {
+            Linear => progress,
+            EaseIn => progress * progress / 1000,
+            EaseOut => 1000 - (1000 - progress) * (1000 - progress) / 1000,
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/* Small xorshift PRNG, seedable so that runs can be reproduced */
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        let mut rng = Rng { state: 0 };
+        rng.seed(seed);
+        rng
+    }
+
+    pub fn seed(&mut self, seed: u32) {
+        /* xorshift gets stuck on zero */
+        self.state = if seed == 0 { 0x9E37_79B9 } else { seed };
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /* Uniform value in min..=max */
+    pub fn range(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+        min + self.next_u32() % (max - min + 1)
+    }
+
+    pub fn chance(&mut self, per_mille: u32) -> bool {
+        self.next_u32() % 1000 < per_mille
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_reproduces_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(7);
+        b.seed(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u32(), b.next_u32());
+        }
+        let mut c = Rng::new(43);
+        assert_ne!(Rng::new(42).next_u32(), c.next_u32());
+    }
+
+    #[test]
+    fn range_stays_within_bounds() {
+        let mut rng = Rng::new(1);
+        for _ in 0..1_000 {
+            let value = rng.range(5, 20);
+            assert!((5..=20).contains(&value));
+        }
+        assert_eq!(rng.range(9, 3), 9);
+    }
+}
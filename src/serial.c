@@ -0,0 +1,23 @@
+#include "serial.h"
+
+#include <fcntl.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <unistd.h>
+
+void serial_write(const uint8_t *data, size_t len) {
+    fwrite(data, 1, len, stdout);
+    fflush(stdout);
+}
+
+// Returns whatever was received so far without blocking the main loop
+size_t serial_read(uint8_t *data, size_t len) {
+    static bool nonblocking = false;
+    if (!nonblocking) {
+        int flags = fcntl(STDIN_FILENO, F_GETFL);
+        fcntl(STDIN_FILENO, F_SETFL, flags | O_NONBLOCK);
+        nonblocking = true;
+    }
+    ssize_t res = read(STDIN_FILENO, data, len);
+    return res > 0 ? (size_t)res : 0;
+}
@@ -0,0 +1,14 @@
+use cty;
+
+extern "C" {
+    fn serial_write(data: *const cty::uint8_t, len: cty::size_t);
+    fn serial_read(data: *mut cty::uint8_t, len: cty::size_t) -> cty::size_t;
+}
+
+pub fn write(data: &[u8]) {
+    unsafe { serial_write(data.as_ptr(), data.len() as cty::size_t) };
+}
+
+pub fn read(data: &mut [u8]) -> usize {
+    unsafe { serial_read(data.as_mut_ptr(), data.len() as cty::size_t) as usize }
+}
@@ -1,31 +1,50 @@
-const MAX_INTENSITY: u8 = 20;
-const MAX_AREA: u32 = 10_000_000;
-const AREA_STEP: u32 = 10_000;
-const MAX_PEAK: u32 = 1_000_000;
-const PEAK_STEP: u32 = 1_000;
-const COOLDOWN_STEP: u32 = 1_000;
-const MAX_DURATION: u32 = 5_000;
-const DURATION_STEP: u32 = 25;
+use crate::cooldown::{Cooldown, CooldownPolicy};
+use crate::eventlog::{EventKind, EventLog};
+use crate::limits::{Limits, MIN_STIM_PAUSE};
+use crate::lock::Lock;
+use crate::orgasm::OrgasmAction;
+use crate::param::{ParamKind, Setting, PARAMS};
+use crate::pattern::Pattern;
+use crate::profile::PROFILES;
+use crate::program::{Program, ProgramKind, ProgramResult, ProgramState};
+use crate::ramp::RampCurve;
+use crate::stats::SessionStats;
+use crate::tease::Tease;
+use log::info;
+
+pub const MAX_INTENSITY: u8 = 20;
+/* Settings are saved once they have not changed for this long */
+const SETTINGS_SAVE_DELAY: u32 = 5_000;
 
 pub struct Hysteresis {
     entry_time: u32,
+    duration: u32,
 }
 
 impl Hysteresis {
     pub fn new() -> Hysteresis {
-        Hysteresis { entry_time: 0 }
+        Hysteresis {
+            entry_time: 0,
+            duration: 0,
+        }
+    }
+
+    pub fn enter(&mut self, time: u32, duration: u32) {
+        self.entry_time = time;
+        self.duration = duration;
     }
 
-    pub fn enter(&mut self, time: u32) {
-        self.entry_time = time
+    pub fn is_active(&self, time: u32) -> bool {
+        time < (self.entry_time + self.duration)
     }
 
-    pub fn is_active(&self, time: u32, cooldown_time: u32) -> bool {
-        time < (self.entry_time + cooldown_time)
+    /* Zero once the cooldown is over */
+    pub fn remaining(&self, time: u32) -> u32 {
+        (self.entry_time + self.duration).saturating_sub(time)
     }
 
-    pub fn remaining(&self, time: u32, cooldown_time: u32) -> u32 {
-        self.entry_time + cooldown_time - time
+    pub fn duration(&self) -> u32 {
+        self.duration
     }
 }
 
@@ -38,28 +57,355 @@ pub struct State {
     pub peak_value_thresh: u32,
     pub peak_release_time_thresh: u32,
     pub cooldown_time: u32,
+    pub cooldown_policy: CooldownPolicy,
+    pub cooldown_increment: u32,
+    pub cooldown_factor: u32,
+    pub cooldown_max: u32,
+    pub cooldown_reset_time: u32,
+    pub cooldown: Cooldown,
     pub intensity: u8,
     pub hysteresis: Hysteresis,
     pub stim_start_time: u32,
     pub cur_time_ms: u32,
+    pub auto_start: bool,
+    pub insertion_thresh: u32,
+    pub insertion_settle_time: u32,
+    pub removal_time: u32,
+    /* The sensor was taken out during the session, which carries on once it is back in */
+    pub paused: bool,
+    pub tease_mode: bool,
+    pub tease_cooldown_min: u32,
+    pub tease_cooldown_max: u32,
+    pub tease_intensity_min: u8,
+    pub tease_intensity_max: u8,
+    pub tease_cut_chance: u32,
+    pub tease: Tease,
+    pub resumed: bool,
+    pub resume_ramp_time: u32,
+    pub resume_curve: RampCurve,
+    pub resume_grace_time: u32,
+    pub auto_ramp: bool,
+    pub auto_ramp_rate: u32,
+    pub auto_ramp_floor: u8,
+    pub auto_ramp_ceiling: u8,
+    pub auto_ramp_backoff: u8,
+    /* Where the ramp is at, the intensity setting is left as the user set it */
+    pub auto_ramp_level: u8,
+    auto_ramp_next: u32,
+    pub profile: usize,
+    pub pattern: Pattern,
+    pub pattern_period: u32,
+    pub pattern_depth: u32,
+    pub program_kind: ProgramKind,
+    pub program_edge_goal: u32,
+    pub program_duration: u32,
+    pub program: Program,
+    pub orgasm_action: OrgasmAction,
+    pub orgasm_peak_thresh: u32,
+    pub orgasm_contractions: u32,
+    pub orgasm_min_interval: u32,
+    pub orgasm_max_interval: u32,
+    pub ruin_time: u32,
+    pub rhythm_warning: bool,
+    pub rhythm_thresh: u32,
+    /* Area of the peak the detector is in, 0 outside of one */
+    pub area: u32,
+    pub stats: SessionStats,
+    pub log: EventLog,
+    pub log_to_flash: bool,
+    pub telemetry_interval: u32,
+    pub encoder_inverted: bool,
+    pub encoder_acceleration: bool,
+    pub lock: Lock,
+    /* Set by the emergency stop, nothing starts until it is acknowledged */
+    pub emergency_stopped: bool,
+    pub limits: Limits,
+    /* How long a lock lasts, 0 until unlocked with the code */
+    pub lock_time: u32,
+    sensor_ok: bool,
+    calibration_requested: bool,
+    settings_changed_at: Option<u32>,
 }
 
 impl State {
     pub fn new() -> State {
-        State {
+        let mut state = State {
             ble_connected: false,
             ble_name: "n/a",
             running: false,
             stimulating: false,
-            peak_area_threshold: 200_000,
-            peak_value_thresh: 15_000,
-            peak_release_time_thresh: 500,
-            cooldown_time: 10_000,
-            intensity: 10,
+            peak_area_threshold: 0,
+            peak_value_thresh: 0,
+            peak_release_time_thresh: 0,
+            cooldown_time: 0,
+            cooldown_policy: CooldownPolicy::Fixed,
+            cooldown_increment: 0,
+            cooldown_factor: 0,
+            cooldown_max: 0,
+            cooldown_reset_time: 0,
+            cooldown: Cooldown::new(),
+            intensity: 0,
             hysteresis: Hysteresis::new(),
             stim_start_time: 0,
             cur_time_ms: 0,
+            auto_start: false,
+            insertion_thresh: 0,
+            insertion_settle_time: 0,
+            removal_time: 0,
+            paused: false,
+            tease_mode: false,
+            tease_cooldown_min: 0,
+            tease_cooldown_max: 0,
+            tease_intensity_min: 0,
+            tease_intensity_max: 0,
+            tease_cut_chance: 0,
+            tease: Tease::new(0),
+            resumed: false,
+            resume_ramp_time: 0,
+            resume_curve: RampCurve::Linear,
+            resume_grace_time: 0,
+            auto_ramp: false,
+            auto_ramp_rate: 0,
+            auto_ramp_floor: 0,
+            auto_ramp_ceiling: 0,
+            auto_ramp_backoff: 0,
+            auto_ramp_level: 0,
+            auto_ramp_next: 0,
+            profile: 0,
+            pattern: Pattern::Constant,
+            pattern_period: 0,
+            pattern_depth: 0,
+            program_kind: ProgramKind::Free,
+            program_edge_goal: 0,
+            program_duration: 0,
+            program: Program::new(),
+            orgasm_action: OrgasmAction::Log,
+            orgasm_peak_thresh: 0,
+            orgasm_contractions: 0,
+            orgasm_min_interval: 0,
+            orgasm_max_interval: 0,
+            ruin_time: 0,
+            rhythm_warning: false,
+            rhythm_thresh: 0,
+            area: 0,
+            stats: SessionStats::new(),
+            log: EventLog::new(),
+            log_to_flash: false,
+            telemetry_interval: 0,
+            encoder_inverted: false,
+            encoder_acceleration: false,
+            lock: Lock::new(),
+            emergency_stopped: false,
+            limits: Limits::new(),
+            lock_time: 0,
+            sensor_ok: true,
+            calibration_requested: false,
+            settings_changed_at: None,
+        };
+        /* Every setting starts out at its default from PARAMS */
+        for param in PARAMS.iter() {
+            state.put_setting(param.setting, param.default);
+        }
+        state
+    }
+
+    pub fn load_profile(&mut self, index: usize) {
+        let profile = &PROFILES[index % PROFILES.len()];
+        self.profile = index % PROFILES.len();
+        self.pattern = profile.pattern;
+        self.pattern_period = profile.pattern_period;
+        self.pattern_depth = profile.pattern_depth;
+        info!("Loaded profile {}", profile.name);
+    }
+
+    pub fn log_event(&mut self, kind: EventKind) {
+        self.log.record(self.cur_time_ms, kind);
+    }
+
+    pub fn setting_changed(&mut self, setting: Setting, value: u32) {
+        self.log_event(EventKind::SettingChanged { setting, value });
+        self.settings_changed_at = Some(self.cur_time_ms);
+    }
+
+    /* Current value of a setting, as logged and reported on the console */
+    pub fn get_setting(&self, setting: Setting) -> u32 {
+        match setting {
+            Setting::Sensitivity => self.peak_value_thresh,
+            Setting::Density => self.peak_area_threshold,
+            Setting::Duration => self.peak_release_time_thresh,
+            Setting::Cooldown => self.cooldown_time,
+            Setting::CooldownPolicy => self.cooldown_policy as u32,
+            Setting::RampTime => self.resume_ramp_time,
+            Setting::RampCurve => self.resume_curve as u32,
+            Setting::Grace => self.resume_grace_time,
+            Setting::Intensity => self.intensity as u32,
+            Setting::AutoStart => self.auto_start as u32,
+            Setting::Tease => self.tease_mode as u32,
+            Setting::AutoRamp => self.auto_ramp as u32,
+            Setting::Profile => self.profile as u32,
+            Setting::Program => self.program_kind as u32,
+            Setting::OrgasmAction => self.orgasm_action as u32,
+            Setting::RhythmWarning => self.rhythm_warning as u32,
+            Setting::LogToFlash => self.log_to_flash as u32,
+            Setting::Telemetry => self.telemetry_interval,
+            Setting::EncoderInvert => self.encoder_inverted as u32,
+            Setting::EncoderAcceleration => self.encoder_acceleration as u32,
+            Setting::LockTime => self.lock_time,
+            Setting::MaxSession => self.limits.max_session,
+            Setting::MaxStimulation => self.limits.max_stimulation,
+            Setting::BreakAfter => self.limits.break_after,
+            Setting::BreakLength => self.limits.break_length,
+            /* The ceiling of the loaded profile */
+            Setting::IntensityCeiling => self.limits.ceiling(self.profile) as u32,
+            Setting::InsertionThreshold => self.insertion_thresh,
+            Setting::InsertionSettle => self.insertion_settle_time,
+            Setting::RemovalTime => self.removal_time,
+            Setting::TeaseCooldownMin => self.tease_cooldown_min,
+            Setting::TeaseCooldownMax => self.tease_cooldown_max,
+            Setting::TeaseIntensityMin => self.tease_intensity_min as u32,
+            Setting::TeaseIntensityMax => self.tease_intensity_max as u32,
+            Setting::TeaseCutChance => self.tease_cut_chance,
+            Setting::AutoRampRate => self.auto_ramp_rate,
+            Setting::AutoRampFloor => self.auto_ramp_floor as u32,
+            Setting::AutoRampCeiling => self.auto_ramp_ceiling as u32,
+            Setting::AutoRampBackoff => self.auto_ramp_backoff as u32,
+            Setting::ProgramEdgeGoal => self.program_edge_goal,
+            Setting::ProgramDuration => self.program_duration,
+            Setting::CooldownIncrement => self.cooldown_increment,
+            Setting::CooldownFactor => self.cooldown_factor,
+            Setting::CooldownMax => self.cooldown_max,
+            Setting::CooldownReset => self.cooldown_reset_time,
+            Setting::OrgasmThreshold => self.orgasm_peak_thresh,
+            Setting::OrgasmContractions => self.orgasm_contractions,
+            Setting::OrgasmMinInterval => self.orgasm_min_interval,
+            Setting::OrgasmMaxInterval => self.orgasm_max_interval,
+            Setting::RuinTime => self.ruin_time,
+            Setting::RhythmThreshold => self.rhythm_thresh,
+        }
+    }
+
+    /* The single place settings are written, the value is already within the parameter's limits */
+    fn put_setting(&mut self, setting: Setting, value: u32) {
+        match setting {
+            Setting::Sensitivity => self.peak_value_thresh = value,
+            Setting::Density => self.peak_area_threshold = value,
+            Setting::Duration => self.peak_release_time_thresh = value,
+            Setting::Cooldown => self.cooldown_time = value,
+            Setting::CooldownPolicy => self.cooldown_policy = CooldownPolicy::from_index(value),
+            Setting::RampTime => self.resume_ramp_time = value,
+            Setting::RampCurve => self.resume_curve = RampCurve::from_index(value),
+            Setting::Grace => self.resume_grace_time = value,
+            Setting::Intensity => self.intensity = value as u8,
+            Setting::AutoStart => self.auto_start = value != 0,
+            Setting::Tease => self.tease_mode = value != 0,
+            Setting::AutoRamp => self.auto_ramp = value != 0,
+            Setting::Profile => self.load_profile(value as usize),
+            Setting::Program => self.program_kind = ProgramKind::from_index(value),
+            Setting::OrgasmAction => self.orgasm_action = OrgasmAction::from_index(value),
+            Setting::RhythmWarning => self.rhythm_warning = value != 0,
+            Setting::LogToFlash => self.log_to_flash = value != 0,
+            Setting::Telemetry => self.telemetry_interval = value,
+            Setting::EncoderInvert => self.encoder_inverted = value != 0,
+            Setting::EncoderAcceleration => self.encoder_acceleration = value != 0,
+            Setting::LockTime => self.lock_time = value,
+            Setting::MaxSession => self.limits.max_session = value,
+            Setting::MaxStimulation => self.limits.max_stimulation = value,
+            Setting::BreakAfter => self.limits.break_after = value,
+            Setting::BreakLength => self.limits.break_length = value,
+            Setting::IntensityCeiling => self.limits.ceilings[self.profile] = value as u8,
+            Setting::InsertionThreshold => self.insertion_thresh = value,
+            Setting::InsertionSettle => self.insertion_settle_time = value,
+            Setting::RemovalTime => self.removal_time = value,
+            Setting::TeaseCooldownMin => self.tease_cooldown_min = value,
+            Setting::TeaseCooldownMax => self.tease_cooldown_max = value,
+            Setting::TeaseIntensityMin => self.tease_intensity_min = value as u8,
+            Setting::TeaseIntensityMax => self.tease_intensity_max = value as u8,
+            Setting::TeaseCutChance => self.tease_cut_chance = value,
+            Setting::AutoRampRate => self.auto_ramp_rate = value,
+            Setting::AutoRampFloor => self.auto_ramp_floor = value as u8,
+            Setting::AutoRampCeiling => self.auto_ramp_ceiling = value as u8,
+            Setting::AutoRampBackoff => self.auto_ramp_backoff = value as u8,
+            Setting::ProgramEdgeGoal => self.program_edge_goal = value,
+            Setting::ProgramDuration => self.program_duration = value,
+            Setting::CooldownIncrement => self.cooldown_increment = value,
+            Setting::CooldownFactor => self.cooldown_factor = value,
+            Setting::CooldownMax => self.cooldown_max = value,
+            Setting::CooldownReset => self.cooldown_reset_time = value,
+            Setting::OrgasmThreshold => self.orgasm_peak_thresh = value,
+            Setting::OrgasmContractions => self.orgasm_contractions = value,
+            Setting::OrgasmMinInterval => self.orgasm_min_interval = value,
+            Setting::OrgasmMaxInterval => self.orgasm_max_interval = value,
+            Setting::RuinTime => self.ruin_time = value,
+            Setting::RhythmThreshold => self.rhythm_thresh = value,
+        }
+    }
+
+    /* Sets a setting, clamping values and rejecting options that do not exist */
+    pub fn set_setting(&mut self, setting: Setting, value: u32) -> bool {
+        let param = setting.param();
+        let value = match param.kind {
+            ParamKind::Value => param.clamp(value),
+            _ if value < param.min || value > param.max => return false,
+            _ => value,
+        };
+        self.put_setting(setting, value);
+        self.setting_changed(setting, value);
+        true
+    }
+
+    /* Moves a setting by a number of steps, as the encoder does on its screen */
+    pub fn adjust_setting(&mut self, setting: Setting, steps: i32) -> bool {
+        let param = setting.param();
+        let value = param.stepped(self.get_setting(setting), steps);
+        if value != self.get_setting(setting) {
+            self.put_setting(setting, value);
+            self.setting_changed(setting, value);
+        }
+        true
+    }
+
+    /* Flips a toggle or moves a choice to its next option, as clicking a toggle's row does */
+    pub fn cycle_setting(&mut self, setting: Setting) -> bool {
+        let param = setting.param();
+        if matches!(param.kind, ParamKind::Value) {
+            return false;
+        }
+        let value = param.cycled(self.get_setting(setting));
+        self.put_setting(setting, value);
+        self.setting_changed(setting, value);
+        true
+    }
+
+    /* Applies a stored value without logging it as a change */
+    pub fn restore_setting(&mut self, setting: Setting, value: u32) {
+        self.put_setting(setting, setting.param().clamp(value));
+    }
+
+    /* True once after settings changed and then were left alone for a while */
+    pub fn take_settings_save(&mut self, time: u32) -> bool {
+        match self.settings_changed_at {
+            Some(since) if time.wrapping_sub(since) >= SETTINGS_SAVE_DELAY => {
+                self.settings_changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /* Re-learn the sensor baseline on the next sample */
+    pub fn request_calibration(&mut self) {
+        self.calibration_requested = true;
+    }
+
+    pub fn take_calibration_request(&mut self) -> bool {
+        core::mem::replace(&mut self.calibration_requested, false)
+    }
+
+    pub fn sensor_read(&mut self, ok: bool) {
+        if !ok && self.sensor_ok {
+            self.log_event(EventKind::SensorFault);
         }
+        self.sensor_ok = ok;
     }
 
     pub fn set_ble_connected(&mut self, connected: bool) {
@@ -67,6 +413,10 @@ impl State {
             return;
         }
         self.ble_connected = connected;
+        self.log_event(match connected {
+            true => EventKind::ToyConnected,
+            false => EventKind::ToyDisconnected,
+        });
     }
 
     pub fn set_ble_name(&mut self, name: &'static str) {
@@ -76,55 +426,291 @@ impl State {
         self.ble_name = name;
     }
 
-    pub fn area_up(&mut self) {
-        if !(self.peak_area_threshold >= MAX_AREA) {
-            self.peak_area_threshold += AREA_STEP;
+    /* Progress of the resume ramp in per mille, if still ramping */
+    pub fn ramp_progress(&self) -> Option<u32> {
+        let elapsed = self.cur_time_ms.wrapping_sub(self.stim_start_time);
+        if !self.resumed || !self.stimulating || elapsed >= self.resume_ramp_time {
+            return None;
+        }
+        Some(elapsed * 1000 / self.resume_ramp_time)
+    }
+    pub fn grace_remaining(&self, time: u32) -> Option<u32> {
+        let elapsed = time.wrapping_sub(self.stim_start_time);
+        if !self.resumed || !self.stimulating || elapsed >= self.resume_grace_time {
+            return None;
         }
+        Some(self.resume_grace_time - elapsed)
+    }
+    pub fn in_grace(&self, time: u32) -> bool {
+        self.grace_remaining(time).is_some()
     }
-    pub fn area_down(&mut self) {
-        self.peak_area_threshold = self.peak_area_threshold.saturating_sub(AREA_STEP);
+    pub fn next_cooldown(&self, time: u32) -> u32 {
+        self.cooldown.next_duration(time, self)
     }
-    pub fn peak_up(&mut self) {
-        if !(self.peak_value_thresh >= MAX_PEAK) {
-            self.peak_value_thresh += PEAK_STEP;
+    pub fn edge(&mut self, time: u32, area: u32, peak: u32) {
+        self.stats.edge(time, area, peak);
+        self.log.record(time, EventKind::Edge { area, peak });
+        if let ProgramResult::End = self.program.edge(self.program_kind, self.program_edge_goal) {
+            self.end_session();
+            return;
+        }
+        let duration = if self.tease_mode {
+            self.tease
+                .cooldown(self.tease_cooldown_min, self.tease_cooldown_max)
+        } else {
+            self.next_cooldown(time)
+        };
+        self.cooldown.edge(time, self.cooldown_reset_time);
+        self.hysteresis.enter(time, duration);
+        self.log.record(time, EventKind::CooldownStart { duration });
+        if self.auto_ramp {
+            self.auto_ramp_level = self
+                .auto_ramp_level
+                .saturating_sub(self.auto_ramp_backoff)
+                .max(self.auto_ramp_floor);
+            info!("Auto ramp: backing off to {}", self.auto_ramp_level);
         }
     }
-    pub fn peak_down(&mut self) {
-        self.peak_value_thresh = self.peak_value_thresh.saturating_sub(PEAK_STEP);
+    fn auto_ramp_interval(&self) -> u32 {
+        60_000 / self.auto_ramp_rate.max(1)
     }
-    pub fn duration_up(&mut self) {
-        if !(self.peak_release_time_thresh >= MAX_DURATION) {
-            self.peak_release_time_thresh += DURATION_STEP;
+    fn start_phase(&mut self) {
+        self.auto_ramp_next = self.cur_time_ms + self.auto_ramp_interval();
+        if self.tease_mode {
+            self.tease.start_phase(
+                self.cur_time_ms,
+                self.tease_intensity_min,
+                self.tease_intensity_max,
+            );
         }
     }
-    pub fn duration_down(&mut self) {
-        self.peak_release_time_thresh = self.peak_release_time_thresh.saturating_sub(DURATION_STEP);
+    pub fn orgasm(&mut self, time: u32) {
+        info!("Orgasm detected");
+        self.stats.orgasms += 1;
+        self.log.record(time, EventKind::Orgasm);
+        if let ProgramResult::End = self.program.orgasm() {
+            self.end_session();
+            return;
+        }
+        match self.orgasm_action {
+            OrgasmAction::Log => {}
+            OrgasmAction::EndSession => self.end_session(),
+            OrgasmAction::Ruin => {
+                info!("Ruining: stopping stimulation for {}ms", self.ruin_time);
+                self.hysteresis.enter(time, self.ruin_time);
+                self.log.record(
+                    time,
+                    EventKind::CooldownStart {
+                        duration: self.ruin_time,
+                    },
+                );
+                self.stop_stim();
+            }
+        }
     }
-    pub fn cooldown_up(&mut self) {
-        self.cooldown_time += COOLDOWN_STEP;
+    pub fn detection_enabled(&self) -> bool {
+        self.program.detection_enabled()
     }
-    pub fn cooldown_down(&mut self) {
-        if self.cooldown_time == 0 {
+    pub fn end_session(&mut self) {
+        if !self.running {
             return;
         }
-        self.cooldown_time -= COOLDOWN_STEP;
+        info!("Session ended");
+        self.running = false;
+        self.stimulating = false;
+        self.stats.stop(self.cur_time_ms);
+        self.log_event(EventKind::SessionStop);
+        self.log.request_dump();
+        /* Keep a finished program around so its result stays visible */
+        if self.program.state != ProgramState::Done {
+            self.program.stop();
+        }
+    }
+    pub fn update(&mut self) {
+        let time = self.cur_time_ms;
+        if self.lock.expire(time) {
+            info!("Lock expired");
+            self.log_event(EventKind::Unlocked);
+        }
+        self.check_limits(time);
+        if self.running {
+            let res = self
+                .program
+                .update(time, self.program_kind, self.program_duration);
+            if let ProgramResult::End = res {
+                self.end_session();
+            }
+        }
+        /* Tease cuts can only stop stimulation, never resume it */
+        if self.tease_mode
+            && self.running
+            && self.stimulating
+            && self.tease.should_cut(time, self.tease_cut_chance)
+        {
+            info!("Tease: cutting stimulation");
+            let duration = self
+                .tease
+                .cooldown(self.tease_cooldown_min, self.tease_cooldown_max);
+            self.hysteresis.enter(time, duration);
+            self.log.record(time, EventKind::CooldownStart { duration });
+            self.stop_stim();
+        }
+        /* Slowly raise the intensity while no edge is detected */
+        if self.auto_ramp && self.running && self.stimulating && time >= self.auto_ramp_next {
+            self.auto_ramp_next = time + self.auto_ramp_interval();
+            if self.auto_ramp_level < self.auto_ramp_ceiling {
+                self.auto_ramp_level += 1;
+            }
+        }
+    }
+    /* Safety limits apply whatever the detector, programs or manual control do */
+    fn check_limits(&mut self, time: u32) {
+        let max_session = self.limits.max_session;
+        if self.running
+            && max_session > 0
+            && time.wrapping_sub(self.stats.start_time) >= max_session
+        {
+            info!("Limit: session length reached");
+            self.log_event(EventKind::LimitReached {
+                setting: Setting::MaxSession,
+            });
+            self.end_session();
+        }
+        let max_stimulation = self.limits.max_stimulation;
+        if self.stimulating
+            && max_stimulation > 0
+            && time.wrapping_sub(self.stim_start_time) >= max_stimulation
+        {
+            info!("Limit: continuous stimulation reached, cooling down");
+            self.log_event(EventKind::LimitReached {
+                setting: Setting::MaxStimulation,
+            });
+            self.pause_stim(time, self.next_cooldown(time).max(MIN_STIM_PAUSE));
+        }
+        if self.limits.track(time, self.stimulating) {
+            info!("Limit: taking a break");
+            self.log_event(EventKind::LimitReached {
+                setting: Setting::BreakAfter,
+            });
+            self.pause_stim(time, self.limits.break_length);
+        }
+    }
+    /* Stops stimulation and keeps the detector from resuming it for a while */
+    fn pause_stim(&mut self, time: u32, duration: u32) {
+        self.hysteresis.enter(time, duration);
+        self.log.record(time, EventKind::CooldownStart { duration });
+        self.stop_stim();
     }
     pub fn toggle(&mut self) {
+        if !self.running && self.emergency_stopped {
+            return;
+        }
+        if !self.running {
+            self.cooldown.reset();
+            self.stats.start(self.cur_time_ms);
+            self.stats.stim_start(self.cur_time_ms);
+            self.log_event(EventKind::SessionStart);
+        } else {
+            self.stats.stop(self.cur_time_ms);
+            self.log_event(EventKind::SessionStop);
+            self.log.request_dump();
+        }
         self.running = !self.running;
-        self.stimulating = self.running;
+        /* A forced break holds a new session back until it is over */
+        self.stimulating = self.running && !self.limits.on_break(self.cur_time_ms);
+        self.stim_start_time = self.cur_time_ms;
+        self.resumed = false;
+        self.paused = false;
+        if self.running && self.auto_ramp {
+            self.auto_ramp_level = self.auto_ramp_floor;
+        }
+        if self.running {
+            self.program.start(self.cur_time_ms, self.program_kind);
+            self.start_phase();
+        } else {
+            self.program.stop();
+        }
+    }
+    /* Stimulation waits while the sensor is out, as edges can not be detected, but the
+     * session and its cooldowns carry on */
+    pub fn pause(&mut self) {
+        if self.running && !self.paused {
+            info!("Session paused");
+            self.paused = true;
+            self.stop_stim();
+        }
+    }
+    /* Picks up a paused session, after the cooldown if one is still running */
+    pub fn resume(&mut self) {
+        if !self.paused {
+            return;
+        }
+        info!("Session resumed");
+        self.paused = false;
+        if !self.hysteresis.is_active(self.cur_time_ms) {
+            self.start_stim();
+        }
+    }
+    /* Makes the settings read-only until unlocked with the same code or the lock timer runs out */
+    pub fn lock(&mut self, code: u32) -> bool {
+        if !self.lock.lock(code, self.cur_time_ms, self.lock_time) {
+            return false;
+        }
+        info!("Settings locked");
+        self.log_event(EventKind::Locked {
+            duration: self.lock_time,
+        });
+        true
+    }
+    pub fn unlock(&mut self, code: u32) -> bool {
+        if !self.lock.unlock(code, self.cur_time_ms) {
+            return false;
+        }
+        info!("Settings unlocked");
+        self.log_event(EventKind::Unlocked);
+        true
+    }
+    /* Stops the session and all stimulation right away */
+    pub fn emergency_stop(&mut self) {
+        info!("Emergency stop");
+        self.emergency_stopped = true;
+        self.end_session();
+        self.stop_stim();
+        self.log_event(EventKind::EmergencyStop);
+    }
+    pub fn acknowledge_stop(&mut self) -> bool {
+        if !self.emergency_stopped {
+            return false;
+        }
+        self.emergency_stopped = false;
+        self.log_event(EventKind::EmergencyStopAcknowledged);
+        true
     }
     pub fn stop_stim(&mut self) {
         if !self.stimulating {
             return;
         }
         self.stimulating = false;
+        if self.running {
+            self.stats.stim_stop(self.cur_time_ms);
+        }
     }
     pub fn start_stim(&mut self) {
-        if self.stimulating {
+        if self.stimulating
+            || self.paused
+            || self.emergency_stopped
+            || self.limits.on_break(self.cur_time_ms)
+        {
             return;
         }
         self.stimulating = true;
+        if self.running {
+            self.stats.stim_start(self.cur_time_ms);
+            self.log_event(EventKind::CooldownEnd);
+        }
         self.stim_start_time = self.cur_time_ms;
+        self.resumed = self.running;
+        self.start_phase();
     }
     pub fn stop_stim_manual(&mut self) {
         if !self.running {
@@ -136,23 +722,231 @@ impl State {
             self.start_stim();
         }
     }
-    pub fn intensity_up(&mut self) {
-        if !(self.intensity >= MAX_INTENSITY) {
-            self.intensity += 1;
-        }
-    }
-    pub fn intensity_down(&mut self) {
-        if self.intensity == 0 {
-            return;
+
+    /* Intensity before patterns, ramps and limits shape it */
+    pub fn base_intensity(&self) -> u8 {
+        match self.running && self.auto_ramp {
+            true => self.auto_ramp_level,
+            false => self.intensity,
         }
-        self.intensity -= 1;
-        if self.stimulating {}
     }
 
     pub fn get_cur_intensity(&self) -> u8 {
-        if !self.stimulating {
+        if !self.stimulating || self.emergency_stopped {
             return 0;
         }
-        return self.intensity;
+        let intensity = if self.running && self.tease_mode {
+            self.tease.intensity
+        } else {
+            self.base_intensity()
+        };
+        /* Patterns only shape stimulation, a stop always wins */
+        let intensity = self.pattern.apply(
+            intensity,
+            self.cur_time_ms.wrapping_sub(self.stim_start_time),
+            self.pattern_period,
+            self.pattern_depth,
+        );
+        let intensity = match self.ramp_progress() {
+            Some(progress) => (intensity as u32 * self.resume_curve.apply(progress) / 1000) as u8,
+            None => intensity,
+        };
+        intensity.min(self.limits.ceiling(self.profile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{dispatch, Command};
+    use crate::eventlog::Event;
+
+    fn session(start: u32) -> State {
+        let mut state = State::new();
+        state.cur_time_ms = start;
+        state.toggle();
+        state
+    }
+
+    #[test]
+    fn pause_keeps_session() {
+        let mut state = State::new();
+        state.cur_time_ms = 1_000;
+        dispatch(&mut state, Command::Start);
+        assert!(state.running && state.stimulating);
+
+        state.cur_time_ms = 5_000;
+        dispatch(&mut state, Command::Pause);
+        assert!(state.running && state.paused && !state.stimulating);
+        /* The detector can not resume stimulation while the sensor is out */
+        state.start_stim();
+        assert!(!state.stimulating);
+
+        state.cur_time_ms = 9_000;
+        dispatch(&mut state, Command::Start);
+        assert!(state.running && !state.paused && state.stimulating);
+        assert_eq!(state.stats.start_time, 1_000);
+        let stopped = |event: &Event| event.kind.name() == "session_stop";
+        assert!(!state.log.newer_than(0).any(stopped));
+
+        dispatch(&mut state, Command::Pause);
+        dispatch(&mut state, Command::Stop);
+        assert!(!state.running && !state.paused);
+    }
+
+    #[test]
+    fn resume_keeps_cooldown() {
+        let mut state = session(1_000);
+        state.edge(2_000, 300_000, 5_000);
+        state.stop_stim();
+        state.cur_time_ms = 2_500;
+        dispatch(&mut state, Command::Pause);
+        state.cur_time_ms = 3_000;
+        dispatch(&mut state, Command::Start);
+        assert!(!state.paused && !state.stimulating);
+    }
+
+    #[test]
+    fn auto_ramp_leaves_intensity_setting() {
+        let mut state = State::new();
+        state.set_setting(Setting::Intensity, 12);
+        state.set_setting(Setting::AutoRamp, 1);
+        state.auto_ramp_rate = 60;
+        state.auto_ramp_floor = 3;
+        state.auto_ramp_ceiling = 18;
+        state.auto_ramp_backoff = 2;
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        assert_eq!(state.base_intensity(), 3);
+
+        run_until(&mut state, 6_000);
+        assert_eq!(state.base_intensity(), 8);
+        state.edge(6_000, 300_000, 5_000);
+        assert_eq!(state.base_intensity(), 6);
+        assert_eq!(state.get_setting(Setting::Intensity), 12);
+
+        state.toggle();
+        assert_eq!(state.base_intensity(), 12);
+    }
+
+    #[test]
+    fn hysteresis_remaining_ends_at_zero() {
+        let mut hysteresis = Hysteresis::new();
+        hysteresis.enter(1_000, 5_000);
+        assert_eq!(hysteresis.remaining(2_000), 4_000);
+        assert_eq!(hysteresis.remaining(6_000), 0);
+        assert_eq!(hysteresis.remaining(60_000), 0);
+    }
+
+    #[test]
+    fn stop_overrides_pattern() {
+        let mut state = session(1_000);
+        state.pattern = Pattern::Square;
+        state.pattern_period = 1_000;
+        state.pattern_depth = 50;
+        state.cur_time_ms = 1_200;
+        assert_eq!(state.get_cur_intensity(), state.intensity);
+        state.cur_time_ms = 1_700;
+        assert_eq!(state.get_cur_intensity(), state.intensity / 2);
+
+        state.stop_stim();
+        state.cur_time_ms = 2_200;
+        assert_eq!(state.get_cur_intensity(), 0);
+        state.start_stim();
+        state.emergency_stop();
+        assert_eq!(state.get_cur_intensity(), 0);
+    }
+
+    /* Calls update every second from the current time up to `until` */
+    fn run_until(state: &mut State, until: u32) {
+        while state.cur_time_ms < until {
+            state.cur_time_ms = (state.cur_time_ms + 1_000).min(until);
+            state.update();
+        }
+    }
+
+    #[test]
+    fn max_session_ends_session() {
+        let mut state = session(1_000);
+        state.limits.max_session = 60_000;
+        run_until(&mut state, 60_999);
+        assert!(state.running);
+        run_until(&mut state, 61_000);
+        assert!(!state.running && !state.stimulating);
+    }
+
+    #[test]
+    fn max_stimulation_counts_from_session_start() {
+        let mut state = session(100_000);
+        state.limits.max_stimulation = 60_000;
+        run_until(&mut state, 101_000);
+        assert!(state.stimulating);
+        run_until(&mut state, 159_000);
+        assert!(state.stimulating);
+        run_until(&mut state, 160_000);
+        assert!(state.running && !state.stimulating);
+        assert!(state.hysteresis.is_active(160_000));
+    }
+
+    #[test]
+    fn max_stimulation_pauses_without_cooldown() {
+        let mut controller = crate::controller::tests::controller();
+        controller.state.set_setting(Setting::Cooldown, 0);
+        controller.state.limits.max_stimulation = 60_000;
+        controller.update(1_000);
+        dispatch(&mut controller.state, Command::Start);
+        let mut stopped = None;
+        for time in (2_000..200_000).step_by(1_000) {
+            controller.update(time);
+            controller.sensor(Some(1_000_000));
+            match (stopped, controller.state.stimulating) {
+                (None, false) => stopped = Some(time),
+                (Some(since), true) => {
+                    assert_eq!(since, 61_000);
+                    assert_eq!(time - since, MIN_STIM_PAUSE);
+                    return;
+                }
+                _ => {}
+            }
+        }
+        panic!("stimulation stopped at {:?} and never resumed", stopped);
+    }
+
+    #[test]
+    fn forced_break_blocks_stimulation() {
+        let mut state = session(0);
+        state.limits.break_after = 10_000;
+        state.limits.break_length = 60_000;
+        run_until(&mut state, 9_000);
+        assert!(state.stimulating);
+        run_until(&mut state, 10_000);
+        assert!(!state.stimulating && state.limits.on_break(10_000));
+
+        /* Neither the detector, manual control nor a new session can stimulate */
+        state.start_stim();
+        assert!(!state.stimulating);
+        dispatch(&mut state, Command::Stop);
+        dispatch(&mut state, Command::ManualStim(true));
+        assert!(!state.stimulating);
+        dispatch(&mut state, Command::Start);
+        assert!(state.running && !state.stimulating);
+
+        run_until(&mut state, 70_000);
+        assert!(!state.limits.on_break(70_000));
+        state.start_stim();
+        assert!(state.stimulating);
+    }
+
+    #[test]
+    fn ceiling_caps_intensity() {
+        let mut state = session(1_000);
+        state.set_setting(Setting::Intensity, 15);
+        assert_eq!(state.get_cur_intensity(), 15);
+        state.set_setting(Setting::IntensityCeiling, 8);
+        assert_eq!(state.get_cur_intensity(), 8);
+        /* Each profile has its own ceiling */
+        state.set_setting(Setting::Profile, 1);
+        assert_eq!(state.limits.ceiling(1), MAX_INTENSITY);
+        assert_eq!(state.limits.ceiling(0), 8);
     }
 }
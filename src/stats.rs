@@ -0,0 +1,222 @@
+use core::fmt::{Result, Write};
+use heapless::Vec;
+
+const MAX_EDGE_RECORDS: usize = 16;
+const SUMMARY_LINES: usize = 7;
+
+pub struct EdgeRecord {
+    pub time: u32,
+    pub area: u32,
+    pub peak: u32,
+}
+
+pub struct SessionStats {
+    pub start_time: u32,
+    pub end_time: u32,
+    pub stim_total: u32,
+    pub stim_longest: u32,
+    pub cooldown_total: u32,
+    pub edges: u32,
+    pub orgasms: u32,
+    pub first_edge: u32,
+    pub last_edge: u32,
+    pub edge_records: Vec<EdgeRecord, MAX_EDGE_RECORDS>,
+    stim_since: Option<u32>,
+    cooldown_since: Option<u32>,
+}
+
+impl SessionStats {
+    pub fn new() -> SessionStats {
+        SessionStats {
+            start_time: 0,
+            end_time: 0,
+            stim_total: 0,
+            stim_longest: 0,
+            cooldown_total: 0,
+            edges: 0,
+            orgasms: 0,
+            first_edge: 0,
+            last_edge: 0,
+            edge_records: Vec::new(),
+            stim_since: None,
+            cooldown_since: None,
+        }
+    }
+
+    pub fn start(&mut self, time: u32) {
+        *self = SessionStats::new();
+        self.start_time = time;
+        self.end_time = time;
+    }
+
+    pub fn stop(&mut self, time: u32) {
+        self.stim_stop(time);
+        if let Some(since) = self.cooldown_since.take() {
+            self.cooldown_total += time.wrapping_sub(since);
+        }
+        self.end_time = time;
+    }
+
+    pub fn stim_start(&mut self, time: u32) {
+        if let Some(since) = self.cooldown_since.take() {
+            self.cooldown_total += time.wrapping_sub(since);
+        }
+        if self.stim_since.is_none() {
+            self.stim_since = Some(time);
+        }
+    }
+
+    pub fn stim_stop(&mut self, time: u32) {
+        if let Some(since) = self.stim_since.take() {
+            let duration = time.wrapping_sub(since);
+            self.stim_total += duration;
+            self.stim_longest = self.stim_longest.max(duration);
+            self.cooldown_since = Some(time);
+        }
+    }
+
+    pub fn edge(&mut self, time: u32, area: u32, peak: u32) {
+        if self.edges == 0 {
+            self.first_edge = time;
+        }
+        self.edges += 1;
+        self.last_edge = time;
+        self.edge_records.push(EdgeRecord { time, area, peak }).ok();
+    }
+
+    pub fn length(&self) -> u32 {
+        self.end_time.wrapping_sub(self.start_time)
+    }
+
+    pub fn average_edge_gap(&self) -> u32 {
+        match self.edges {
+            0 | 1 => 0,
+            n => self.last_edge.wrapping_sub(self.first_edge) / (n - 1),
+        }
+    }
+
+    pub fn summary_lines(&self) -> usize {
+        SUMMARY_LINES + self.edge_records.len()
+    }
+
+    pub fn write_summary_line<W: Write>(&self, index: usize, w: &mut W) -> Result {
+        match index {
+            0 => write_duration(w, "Length", self.length()),
+            1 => write_duration(w, "Stim", self.stim_total),
+            2 => write_duration(w, "Longest", self.stim_longest),
+            3 => write_duration(w, "Cooldown", self.cooldown_total),
+            4 => write!(w, "Edges {}", self.edges),
+            5 => write_duration(w, "Avg gap", self.average_edge_gap()),
+            6 => write!(w, "Orgasms {}", self.orgasms),
+            n => match self.edge_records.get(n - SUMMARY_LINES) {
+                Some(edge) => write!(
+                    w,
+                    "#{} A{}k P{}k",
+                    n - SUMMARY_LINES + 1,
+                    edge.area / 1_000,
+                    edge.peak / 1_000
+                ),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+fn write_duration<W: Write>(w: &mut W, name: &str, ms: u32) -> Result {
+    let s = ms / 1_000;
+    write!(w, "{} {}:{:02}", name, s / 60, s % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::String;
+
+    fn line(stats: &SessionStats, index: usize) -> String<32> {
+        let mut text = String::new();
+        stats.write_summary_line(index, &mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn counts_edges() {
+        let mut stats = SessionStats::new();
+        stats.start(1_000);
+        assert_eq!(stats.average_edge_gap(), 0);
+        stats.edge(61_000, 200_000, 30_000);
+        assert_eq!(stats.average_edge_gap(), 0);
+        stats.edge(121_000, 250_000, 35_000);
+        stats.edge(241_000, 300_000, 40_000);
+        assert_eq!(stats.edges, 3);
+        assert_eq!(stats.first_edge, 61_000);
+        assert_eq!(stats.average_edge_gap(), 90_000);
+        assert_eq!(line(&stats, 4), "Edges 3");
+        assert_eq!(line(&stats, 5), "Avg gap 1:30");
+        assert_eq!(line(&stats, SUMMARY_LINES + 2), "#3 A300k P40k");
+        assert_eq!(stats.summary_lines(), SUMMARY_LINES + 3);
+    }
+
+    #[test]
+    fn keeps_first_edge_records() {
+        let mut stats = SessionStats::new();
+        for i in 0..MAX_EDGE_RECORDS as u32 + 4 {
+            stats.edge(i * 1_000, i, i);
+        }
+        assert_eq!(stats.edges, MAX_EDGE_RECORDS as u32 + 4);
+        assert_eq!(stats.edge_records.len(), MAX_EDGE_RECORDS);
+        assert_eq!(stats.edge_records[0].time, 0);
+    }
+
+    #[test]
+    fn counts_orgasms() {
+        let mut state = crate::state::State::new();
+        state.cur_time_ms = 1_000;
+        state.toggle();
+        state.orgasm(2_000);
+        state.orgasm(3_000);
+        assert_eq!(state.stats.orgasms, 2);
+        assert_eq!(line(&state.stats, 6), "Orgasms 2");
+        /* A new session starts counting over */
+        state.toggle();
+        state.toggle();
+        assert_eq!(state.stats.orgasms, 0);
+    }
+
+    #[test]
+    fn splits_stimulation_and_cooldown() {
+        let mut stats = SessionStats::new();
+        stats.start(0);
+        stats.stim_start(0);
+        stats.stim_stop(30_000);
+        stats.stim_start(40_000);
+        /* Starting twice keeps the earlier start */
+        stats.stim_start(50_000);
+        stats.stim_stop(130_000);
+        stats.stim_stop(140_000);
+        stats.stim_start(150_000);
+        stats.stop(170_000);
+
+        assert_eq!(stats.length(), 170_000);
+        assert_eq!(stats.stim_total, 30_000 + 90_000 + 20_000);
+        assert_eq!(stats.cooldown_total, 10_000 + 20_000);
+        assert_eq!(stats.stim_longest, 90_000);
+        assert_eq!(line(&stats, 0), "Length 2:50");
+        assert_eq!(line(&stats, 1), "Stim 2:20");
+        assert_eq!(line(&stats, 2), "Longest 1:30");
+        assert_eq!(line(&stats, 3), "Cooldown 0:30");
+    }
+
+    #[test]
+    fn session_ending_in_cooldown_counts_it() {
+        let mut stats = SessionStats::new();
+        stats.start(0);
+        stats.stim_start(0);
+        stats.stim_stop(10_000);
+        stats.stop(25_000);
+        assert_eq!(stats.stim_total, 10_000);
+        assert_eq!(stats.cooldown_total, 15_000);
+        /* Stopping twice does not count anything again */
+        stats.stop(30_000);
+        assert_eq!(stats.stim_total + stats.cooldown_total, 25_000);
+    }
+}
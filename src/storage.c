@@ -0,0 +1,41 @@
+#include "storage.h"
+
+#include "esp_log.h"
+#include "nvs.h"
+
+#define STORAGE_NAMESPACE "nogasm"
+
+int storage_write(const char *key, const void *data, size_t len) {
+    nvs_handle_t handle;
+    esp_err_t err = nvs_open(STORAGE_NAMESPACE, NVS_READWRITE, &handle);
+    if (err != ESP_OK) {
+        ESP_LOGE("STORAGE", "Failed to open namespace (error: %d)", err);
+        return err;
+    }
+    err = nvs_set_blob(handle, key, data, len);
+    if (err == ESP_OK) {
+        err = nvs_commit(handle);
+    }
+    if (err != ESP_OK) {
+        ESP_LOGE("STORAGE", "Failed to write %s (error: %d)", key, err);
+    }
+    nvs_close(handle);
+    return err;
+}
+
+// Returns the number of bytes read, or -1 if the key could not be read
+int storage_read(const char *key, void *data, size_t len) {
+    nvs_handle_t handle;
+    esp_err_t err = nvs_open(STORAGE_NAMESPACE, NVS_READONLY, &handle);
+    if (err != ESP_OK) {
+        return -1;
+    }
+    size_t size = len;
+    err = nvs_get_blob(handle, key, data, &size);
+    nvs_close(handle);
+    if (err != ESP_OK) {
+        ESP_LOGW("STORAGE", "Failed to read %s (error: %d)", key, err);
+        return -1;
+    }
+    return (int)size;
+}
@@ -0,0 +1,109 @@
+use core::ffi::CStr;
+use cty;
+
+use crate::eventlog::{EventLog, SERIALIZED_SIZE};
+use crate::limits::{self, Limits};
+use crate::lock::{self, Lock};
+use crate::param;
+use crate::state::State;
+
+extern "C" {
+    fn storage_write(
+        key: *const cty::c_char,
+        data: *const cty::c_void,
+        len: cty::size_t,
+    ) -> cty::c_int;
+    fn storage_read(
+        key: *const cty::c_char,
+        data: *mut cty::c_void,
+        len: cty::size_t,
+    ) -> cty::c_int;
+}
+
+const EVENT_LOG_KEY: &[u8] = b"eventlog\0";
+const SETTINGS_KEY: &[u8] = b"settings\0";
+const LIMITS_KEY: &[u8] = b"limits\0";
+const LOCK_KEY: &[u8] = b"lock\0";
+
+pub fn write(key: &CStr, data: &[u8]) -> bool {
+    let res = unsafe {
+        storage_write(
+            key.as_ptr(),
+            data.as_ptr() as *const cty::c_void,
+            data.len() as cty::size_t,
+        )
+    };
+    return res == 0;
+}
+
+pub fn read(key: &CStr, data: &mut [u8]) -> Option<usize> {
+    let res = unsafe {
+        storage_read(
+            key.as_ptr(),
+            data.as_mut_ptr() as *mut cty::c_void,
+            data.len() as cty::size_t,
+        )
+    };
+    if res < 0 {
+        return None;
+    }
+    Some(res as usize)
+}
+
+pub fn save_event_log(log: &EventLog) -> bool {
+    let mut buf = [0u8; SERIALIZED_SIZE];
+    let len = log.serialize(&mut buf);
+    write(
+        CStr::from_bytes_with_nul(EVENT_LOG_KEY).unwrap(),
+        &buf[..len],
+    )
+}
+
+pub fn load_event_log(log: &mut EventLog) {
+    let mut buf = [0u8; SERIALIZED_SIZE];
+    if let Some(len) = read(CStr::from_bytes_with_nul(EVENT_LOG_KEY).unwrap(), &mut buf) {
+        log.deserialize(&buf[..len]);
+    }
+}
+
+pub fn save_settings(state: &State) -> bool {
+    let mut buf = [0u8; param::SERIALIZED_SIZE];
+    let len = param::serialize(state, &mut buf);
+    write(
+        CStr::from_bytes_with_nul(SETTINGS_KEY).unwrap(),
+        &buf[..len],
+    )
+}
+
+pub fn load_settings(state: &mut State) {
+    let mut buf = [0u8; param::SERIALIZED_SIZE];
+    if let Some(len) = read(CStr::from_bytes_with_nul(SETTINGS_KEY).unwrap(), &mut buf) {
+        param::deserialize(state, &buf[..len]);
+    }
+}
+
+pub fn save_limits(limits: &Limits) -> bool {
+    let mut buf = [0u8; limits::SERIALIZED_SIZE];
+    let len = limits.serialize(&mut buf);
+    write(CStr::from_bytes_with_nul(LIMITS_KEY).unwrap(), &buf[..len])
+}
+
+pub fn load_limits(limits: &mut Limits) {
+    let mut buf = [0u8; limits::SERIALIZED_SIZE];
+    if let Some(len) = read(CStr::from_bytes_with_nul(LIMITS_KEY).unwrap(), &mut buf) {
+        limits.deserialize(&buf[..len]);
+    }
+}
+
+pub fn save_lock(lock: &Lock, time: u32) -> bool {
+    let mut buf = [0u8; lock::SERIALIZED_SIZE];
+    let len = lock.serialize(time, &mut buf);
+    write(CStr::from_bytes_with_nul(LOCK_KEY).unwrap(), &buf[..len])
+}
+
+pub fn load_lock(lock: &mut Lock, time: u32) {
+    let mut buf = [0u8; lock::SERIALIZED_SIZE];
+    if let Some(len) = read(CStr::from_bytes_with_nul(LOCK_KEY).unwrap(), &mut buf) {
+        lock.deserialize(&buf[..len], time);
+    }
+}
@@ -1,34 +1,259 @@
 use embedded_hal::digital::v2::InputPin;
 
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub enum SwitchEvent {
+    Press,
+    Release,
+    /* A short press, reported once no second press followed */
+    Click,
+    /* A second press shortly after a click */
+    DoubleClick,
+    /* Held down for long_press_us */
+    LongPress,
+    /* Every repeat_us while still held after a long press */
+    Repeat,
+}
+
+#[derive(Copy, Clone)]
+pub struct SwitchTimings {
+    pub debounce_us: u64,
+    pub long_press_us: u64,
+    /* How long a click waits for a second press, 0 reports clicks right away */
+    pub double_click_us: u64,
+    pub repeat_us: u64,
+}
+
+impl Default for SwitchTimings {
+    fn default() -> Self {
+        SwitchTimings {
+            debounce_us: 500,
+            long_press_us: 1_500_000,
+            double_click_us: 300_000,
+            repeat_us: 200_000,
+        }
+    }
+}
+
 pub struct DebouncedSwitch<PIN> {
     pin: PIN,
-    debounce_us: u64,
+    timings: SwitchTimings,
     next_check: u64,
     last_val: bool,
+    /* When the next LongPress or Repeat is due while held */
+    next_hold: Option<u64>,
+    /* A click waiting to see whether a second press follows */
+    click_until: Option<u64>,
+    /* The current press already turned into another gesture and does not click */
+    consumed: bool,
+    pending: Option<SwitchEvent>,
 }
 
-impl<'a, SWITCH> DebouncedSwitch<SWITCH>
+impl<SWITCH> DebouncedSwitch<SWITCH>
 where
     SWITCH: InputPin,
 {
-    pub fn new(pin: SWITCH, debounce_us: u64) -> DebouncedSwitch<SWITCH> {
+    pub fn new(pin: SWITCH, timings: SwitchTimings) -> DebouncedSwitch<SWITCH> {
         DebouncedSwitch {
             pin,
+            timings,
             next_check: 0,
-            debounce_us,
             last_val: false,
+            next_hold: None,
+            click_until: None,
+            consumed: false,
+            pending: None,
         }
     }
 
-    pub fn clicked(&mut self, now_us: u64) -> bool {
-        if self.next_check < now_us {
+    pub fn is_held(&self) -> bool {
+        self.last_val
+    }
+
+    /* The press is used for something else (like holding while turning), so it no longer
+     * clicks or long-presses */
+    pub fn consume(&mut self) {
+        if self.last_val {
+            self.consumed = true;
+            self.next_hold = None;
+        }
+    }
+
+    /* Returns at most one event per call, call it at least as often as the timings need */
+    pub fn poll(&mut self, now_us: u64) -> Option<SwitchEvent> {
+        if let Some(event) = self.pending.take() {
+            return Some(event);
+        }
+
+        if self.next_check <= now_us {
             let pin = self.pin.is_low().unwrap_or_default();
             if pin != self.last_val {
                 self.last_val = pin;
-                self.next_check = now_us + self.debounce_us;
-                return pin;
+                self.next_check = now_us + self.timings.debounce_us;
+                return Some(match pin {
+                    true => self.pressed(now_us),
+                    false => self.released(now_us),
+                });
             }
         }
-        false
+
+        match (self.next_hold, self.click_until) {
+            (Some(due), _) if due <= now_us => {
+                self.next_hold = Some(due + self.timings.repeat_us);
+                match self.consumed {
+                    true => Some(SwitchEvent::Repeat),
+                    false => {
+                        self.consumed = true;
+                        Some(SwitchEvent::LongPress)
+                    }
+                }
+            }
+            (_, Some(until)) if until <= now_us => {
+                self.click_until = None;
+                Some(SwitchEvent::Click)
+            }
+            _ => None,
+        }
+    }
+
+    fn pressed(&mut self, now_us: u64) -> SwitchEvent {
+        self.next_hold = Some(now_us + self.timings.long_press_us);
+        self.consumed = false;
+        if self.click_until.take().is_some() {
+            self.consumed = true;
+            self.pending = Some(SwitchEvent::DoubleClick);
+        }
+        SwitchEvent::Press
+    }
+
+    fn released(&mut self, now_us: u64) -> SwitchEvent {
+        self.next_hold = None;
+        if !self.consumed {
+            match self.timings.double_click_us {
+                0 => self.pending = Some(SwitchEvent::Click),
+                wait => self.click_until = Some(now_us + wait),
+            }
+        }
+        SwitchEvent::Release
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use heapless::Vec;
+    use SwitchEvent::*;
+
+    /* Active low like the encoder switch, pressed while the cell is true */
+    struct MockPin<'a>(&'a Cell<bool>);
+
+    impl InputPin for MockPin<'_> {
+        type Error = Infallible;
+        fn is_high(&self) -> Result<bool, Infallible> {
+            Ok(!self.0.get())
+        }
+        fn is_low(&self) -> Result<bool, Infallible> {
+            Ok(self.0.get())
+        }
+    }
+
+    const TIMINGS: SwitchTimings = SwitchTimings {
+        debounce_us: 5_000,
+        long_press_us: 1_000_000,
+        double_click_us: 300_000,
+        repeat_us: 200_000,
+    };
+
+    /* Polls every millisecond, replaying presses as (from, to) in ms, and returns (ms, event) */
+    fn run(
+        timings: SwitchTimings,
+        presses: &[(u64, u64)],
+        until_ms: u64,
+        mut on_ms: impl FnMut(u64, &mut DebouncedSwitch<MockPin>),
+    ) -> Vec<(u64, SwitchEvent), 16> {
+        let level = Cell::new(false);
+        let mut switch = DebouncedSwitch::new(MockPin(&level), timings);
+        let mut events = Vec::new();
+        for ms in 0..until_ms {
+            level.set(presses.iter().any(|&(from, to)| from <= ms && ms < to));
+            on_ms(ms, &mut switch);
+            while let Some(event) = switch.poll(ms * 1_000) {
+                events.push((ms, event)).unwrap();
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn click_after_double_click_window() {
+        let events = run(TIMINGS, &[(10, 100)], 1_000, |_, _| {});
+        assert_eq!(events, [(10, Press), (100, Release), (400, Click)]);
+    }
+
+    #[test]
+    fn click_right_away_without_double_click() {
+        let timings = SwitchTimings {
+            double_click_us: 0,
+            ..TIMINGS
+        };
+        let events = run(timings, &[(10, 100)], 1_000, |_, _| {});
+        assert_eq!(events, [(10, Press), (100, Release), (100, Click)]);
+    }
+
+    #[test]
+    fn double_click() {
+        let events = run(TIMINGS, &[(10, 100), (200, 250)], 1_000, |_, _| {});
+        assert_eq!(
+            events,
+            [
+                (10, Press),
+                (100, Release),
+                (200, Press),
+                (200, DoubleClick),
+                (250, Release)
+            ]
+        );
+    }
+
+    #[test]
+    fn slow_second_press_is_two_clicks() {
+        let events = run(TIMINGS, &[(10, 100), (500, 550)], 1_000, |_, _| {});
+        let clicks = events.iter().filter(|(_, e)| *e == Click).count();
+        assert_eq!(clicks, 2);
+        assert!(!events.iter().any(|(_, e)| *e == DoubleClick));
+    }
+
+    #[test]
+    fn long_press_then_repeat() {
+        let events = run(TIMINGS, &[(0, 1_500)], 2_000, |_, _| {});
+        assert_eq!(
+            events,
+            [
+                (0, Press),
+                (1_000, LongPress),
+                (1_200, Repeat),
+                (1_400, Repeat),
+                (1_500, Release)
+            ]
+        );
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let events = run(TIMINGS, &[(10, 12), (13, 100)], 1_000, |_, _| {});
+        assert_eq!(events, [(10, Press), (100, Release), (400, Click)]);
+    }
+
+    #[test]
+    fn consumed_press_does_not_click() {
+        let events = run(TIMINGS, &[(0, 1_500)], 2_000, |ms, switch| {
+            if ms == 50 {
+                assert!(switch.is_held());
+                switch.consume();
+            }
+        });
+        assert_eq!(events, [(0, Press), (1_500, Release)]);
     }
 }
@@ -0,0 +1,46 @@
+use crate::rng::Rng;
+use log::info;
+
+const CUT_CHECK_INTERVAL_MS: u32 = 1_000;
+
+pub struct Tease {
+    rng: Rng,
+    pub intensity: u8,
+    next_check: u32,
+}
+
+impl Tease {
+    pub fn new(seed: u32) -> Tease {
+        Tease {
+            rng: Rng::new(seed),
+            intensity: 0,
+            next_check: 0,
+        }
+    }
+
+    pub fn seed(&mut self, seed: u32) {
+        self.rng.seed(seed);
+    }
+
+    /* Draw the intensity for the next stimulation phase */
+    pub fn start_phase(&mut self, time: u32, min: u8, max: u8) {
+        self.intensity = self.rng.range(min as u32, max as u32) as u8;
+        self.next_check = time + CUT_CHECK_INTERVAL_MS;
+        info!("Tease: stimulating at {}", self.intensity);
+    }
+
+    pub fn cooldown(&mut self, min: u32, max: u32) -> u32 {
+        let duration = self.rng.range(min, max);
+        info!("Tease: cooling down for {}ms", duration);
+        duration
+    }
+
+    /* Randomly decide to cut stimulation, checked once per interval */
+    pub fn should_cut(&mut self, time: u32, per_mille: u32) -> bool {
+        if time < self.next_check {
+            return false;
+        }
+        self.next_check = time + CUT_CHECK_INTERVAL_MS;
+        self.rng.chance(per_mille)
+    }
+}
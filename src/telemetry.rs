@@ -0,0 +1,490 @@
+use crate::eventlog::{Event, EventKind, EventLog};
+
+/* Largest record payload: type, sequence number and six words */
+const MAX_PAYLOAD: usize = 2 + 6 * 4;
+/* COBS adds one byte per 254, the frame adds the CRC and two delimiters */
+pub const MAX_FRAME: usize = MAX_PAYLOAD + 2 + 1 + 2;
+
+const SAMPLE: u8 = 1;
+const DETECTOR: u8 = 2;
+const INTENSITY: u8 = 3;
+const EVENT: u8 = 4;
+
+/* Telemetry records, each frame is COBS(type, seq, fields..., crc16) with all fields little endian */
+#[derive(Copy, Clone)]
+pub enum Record {
+    Sample {
+        time: u32,
+        raw: u32,
+        filtered: u32,
+        baseline: u32,
+        area: u32,
+        rhythm: u32,
+    },
+    Detector {
+        time: u32,
+        running: bool,
+        stimulating: bool,
+        cooldown: u32,
+        grace: u32,
+        edges: u32,
+    },
+    Intensity {
+        time: u32,
+        output: u8,
+        base: u8,
+    },
+    Event {
+        time: u32,
+        code: u8,
+        a: u32,
+        b: u32,
+    },
+}
+
+impl Record {
+    pub fn event(event: &Event) -> Record {
+        let (code, a, b) = event.kind.encode();
+        Record::Event {
+            time: event.time,
+            code,
+            a,
+            b,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Record::Sample { .. } => "sample",
+            Record::Detector { .. } => "detector",
+            Record::Intensity { .. } => "intensity",
+            Record::Event { .. } => "event",
+        }
+    }
+
+    pub fn time(&self) -> u32 {
+        match *self {
+            Record::Sample { time, .. }
+            | Record::Detector { time, .. }
+            | Record::Intensity { time, .. }
+            | Record::Event { time, .. } => time,
+        }
+    }
+
+    /* The logged event, if this is an event record of a known kind */
+    pub fn event_kind(&self) -> Option<EventKind> {
+        match *self {
+            Record::Event { code, a, b, .. } => EventKind::decode(code, a, b),
+            _ => None,
+        }
+    }
+
+    fn encode(&self, seq: u8, buf: &mut [u8]) -> usize {
+        let mut w = Writer { buf, len: 2 };
+        let kind = match *self {
+            Record::Sample {
+                time,
+                raw,
+                filtered,
+                baseline,
+                area,
+                rhythm,
+            } => {
+                for word in [time, raw, filtered, baseline, area, rhythm] {
+                    w.word(word);
+                }
+                SAMPLE
+            }
+            Record::Detector {
+                time,
+                running,
+                stimulating,
+                cooldown,
+                grace,
+                edges,
+            } => {
+                w.word(time);
+                w.byte(running as u8 | (stimulating as u8) << 1);
+                w.word(cooldown);
+                w.word(grace);
+                w.word(edges);
+                DETECTOR
+            }
+            Record::Intensity { time, output, base } => {
+                w.word(time);
+                w.byte(output);
+                w.byte(base);
+                INTENSITY
+            }
+            Record::Event { time, code, a, b } => {
+                w.word(time);
+                w.byte(code);
+                w.word(a);
+                w.word(b);
+                EVENT
+            }
+        };
+        w.buf[0] = kind;
+        w.buf[1] = seq;
+        w.len
+    }
+
+    fn decode(payload: &[u8]) -> Option<(u8, Record)> {
+        let mut r = Reader {
+            buf: payload.get(2..)?,
+        };
+        let record = match payload[0] {
+            SAMPLE => Record::Sample {
+                time: r.word()?,
+                raw: r.word()?,
+                filtered: r.word()?,
+                baseline: r.word()?,
+                area: r.word()?,
+                rhythm: r.word()?,
+            },
+            DETECTOR => {
+                let time = r.word()?;
+                let flags = r.byte()?;
+                Record::Detector {
+                    time,
+                    running: flags & 1 != 0,
+                    stimulating: flags & 2 != 0,
+                    cooldown: r.word()?,
+                    grace: r.word()?,
+                    edges: r.word()?,
+                }
+            }
+            INTENSITY => Record::Intensity {
+                time: r.word()?,
+                output: r.byte()?,
+                base: r.byte()?,
+            },
+            EVENT => Record::Event {
+                time: r.word()?,
+                code: r.byte()?,
+                a: r.word()?,
+                b: r.word()?,
+            },
+            _ => return None,
+        };
+        if !r.buf.is_empty() {
+            return None;
+        }
+        Some((payload[1], record))
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn byte(&mut self, val: u8) {
+        self.buf[self.len] = val;
+        self.len += 1;
+    }
+
+    fn word(&mut self, val: u32) {
+        self.buf[self.len..self.len + 4].copy_from_slice(&val.to_le_bytes());
+        self.len += 4;
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&val, rest) = self.buf.split_first()?;
+        self.buf = rest;
+        Some(val)
+    }
+
+    fn word(&mut self) -> Option<u32> {
+        let val = u32::from_le_bytes(self.buf.get(..4)?.try_into().ok()?);
+        self.buf = &self.buf[4..];
+        Some(val)
+    }
+}
+
+/* CRC-16/CCITT-FALSE */
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/* Consistent overhead byte stuffing, dst must hold src.len() + src.len() / 254 + 1 bytes */
+pub fn cobs_encode(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut code_idx = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+    for &byte in src {
+        if byte != 0 {
+            dst[len] = byte;
+            len += 1;
+            code += 1;
+        }
+        if byte == 0 || code == 0xFF {
+            dst[code_idx] = code;
+            code_idx = len;
+            len += 1;
+            code = 1;
+        }
+    }
+    dst[code_idx] = code;
+    len
+}
+
+/* Decodes a COBS block without its delimiter, returns None if it is malformed or dst is too small */
+pub fn cobs_decode(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut i = 0;
+    while i < src.len() {
+        let code = src[i] as usize;
+        if code == 0 || i + code > src.len() {
+            return None;
+        }
+        for &byte in &src[i + 1..i + code] {
+            *dst.get_mut(len)? = byte;
+            len += 1;
+        }
+        i += code;
+        if code != 0xFF && i < src.len() {
+            *dst.get_mut(len)? = 0;
+            len += 1;
+        }
+    }
+    Some(len)
+}
+
+/* Splits a frame's contents (without delimiters) back into sequence number and record */
+pub fn decode_frame(frame: &[u8]) -> Option<(u8, Record)> {
+    let mut buf = [0u8; MAX_PAYLOAD + 2];
+    let len = cobs_decode(frame, &mut buf)?;
+    if len < 4 {
+        return None;
+    }
+    let (payload, crc) = buf[..len].split_at(len - 2);
+    if crc16(payload) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return None;
+    }
+    Record::decode(payload)
+}
+
+/* Sender side: paces the samples and numbers the frames so the host can spot drops */
+pub struct Telemetry {
+    seq: u8,
+    next_sample: u32,
+    events_sent: u32,
+}
+
+impl Telemetry {
+    pub fn new() -> Telemetry {
+        Telemetry {
+            seq: 0,
+            next_sample: 0,
+            events_sent: 0,
+        }
+    }
+
+    /* An interval of 0 disables the sample records */
+    pub fn sample_due(&mut self, time: u32, interval: u32) -> bool {
+        if interval == 0 || (time.wrapping_sub(self.next_sample) as i32) < 0 {
+            return false;
+        }
+        self.next_sample = time.wrapping_add(interval);
+        true
+    }
+
+    pub fn encode(&mut self, record: &Record, frame: &mut [u8; MAX_FRAME]) -> usize {
+        let mut payload = [0u8; MAX_PAYLOAD + 2];
+        let mut len = record.encode(self.seq, &mut payload);
+        let crc = crc16(&payload[..len]);
+        payload[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+        len += 2;
+        self.seq = self.seq.wrapping_add(1);
+
+        /* A leading delimiter drops any text output that got in between two frames */
+        frame[0] = 0;
+        let encoded = cobs_encode(&payload[..len], &mut frame[1..]);
+        frame[1 + encoded] = 0;
+        encoded + 2
+    }
+
+    /* Sends the events recorded since the last call */
+    pub fn send_events<F: FnMut(&[u8])>(&mut self, log: &EventLog, out: &mut F) {
+        let mut frame = [0u8; MAX_FRAME];
+        let since = self.events_sent;
+        self.events_sent = log.recorded();
+        for event in log.newer_than(since) {
+            let len = self.encode(&Record::event(event), &mut frame);
+            out(&frame[..len]);
+        }
+    }
+
+    /* Marks all logged events as sent, so enabling telemetry does not replay old ones */
+    pub fn skip_events(&mut self, log: &EventLog) {
+        self.events_sent = log.recorded();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventlog::EventKind;
+
+    fn roundtrip(src: &[u8]) {
+        let mut encoded = [0u8; 700];
+        let len = cobs_encode(src, &mut encoded);
+        assert!(encoded[..len].iter().all(|&byte| byte != 0));
+        let mut decoded = [0u8; 700];
+        assert_eq!(cobs_decode(&encoded[..len], &mut decoded), Some(src.len()));
+        assert_eq!(&decoded[..src.len()], src);
+    }
+
+    #[test]
+    fn cobs_roundtrip() {
+        roundtrip(&[]);
+        roundtrip(&[0]);
+        roundtrip(&[0, 0, 0, 0, 0]);
+        roundtrip(&[1, 2, 0, 3]);
+        /* Runs longer than 254 non-zero bytes are split into blocks */
+        let long: [u8; 600] = core::array::from_fn(|i| (i % 7) as u8 * 40 + 1);
+        roundtrip(&long);
+        let mixed: [u8; 600] = core::array::from_fn(|i| (i % 300) as u8);
+        roundtrip(&mixed);
+    }
+
+    #[test]
+    fn cobs_encodes_zeros() {
+        let mut encoded = [0u8; 8];
+        let len = cobs_encode(&[0; 5], &mut encoded);
+        assert_eq!(&encoded[..len], &[1, 1, 1, 1, 1, 1]);
+        let len = cobs_encode(&[0x11, 0x22, 0, 0x33], &mut encoded);
+        assert_eq!(&encoded[..len], &[3, 0x11, 0x22, 2, 0x33]);
+    }
+
+    #[test]
+    fn cobs_rejects_malformed() {
+        let mut decoded = [0u8; 8];
+        assert_eq!(cobs_decode(&[3, 1], &mut decoded), None);
+        assert_eq!(cobs_decode(&[2, 1, 0, 1], &mut decoded), None);
+        assert_eq!(
+            cobs_decode(&[9, 1, 2, 3, 4, 5, 6, 7, 8], &mut [0u8; 4]),
+            None
+        );
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    fn frame(telemetry: &mut Telemetry, record: &Record) -> ([u8; MAX_FRAME], usize) {
+        let mut frame = [0u8; MAX_FRAME];
+        let len = telemetry.encode(record, &mut frame);
+        (frame, len)
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let mut telemetry = Telemetry::new();
+        let record = Record::Sample {
+            time: 0x0100_0000,
+            raw: 0,
+            filtered: 255,
+            baseline: 0,
+            area: 70_000,
+            rhythm: 1_000,
+        };
+        telemetry.encode(&record, &mut [0u8; MAX_FRAME]);
+        let (frame, len) = frame(&mut telemetry, &record);
+        assert_eq!((frame[0], frame[len - 1]), (0, 0));
+        match decode_frame(&frame[1..len - 1]) {
+            Some((
+                1,
+                Record::Sample {
+                    time,
+                    filtered,
+                    area,
+                    rhythm,
+                    ..
+                },
+            )) => {
+                assert_eq!(
+                    (time, filtered, area, rhythm),
+                    (0x0100_0000, 255, 70_000, 1_000)
+                )
+            }
+            _ => panic!("sample did not decode"),
+        }
+    }
+
+    #[test]
+    fn decode_rejects_bad_crc() {
+        let mut telemetry = Telemetry::new();
+        let record = Record::Intensity {
+            time: 1_234,
+            output: 7,
+            base: 10,
+        };
+        let (frame, len) = frame(&mut telemetry, &record);
+        assert!(decode_frame(&frame[1..len - 1]).is_some());
+        for i in 1..len - 1 {
+            let mut bad = frame;
+            bad[i] ^= 0x01;
+            /* Flipping a byte into a zero breaks the framing instead */
+            if bad[i] != 0 {
+                assert!(
+                    decode_frame(&bad[1..len - 1]).is_none(),
+                    "byte {} flipped",
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn events_are_sent_once() {
+        let mut telemetry = Telemetry::new();
+        let mut log = EventLog::new();
+        log.record(100, EventKind::Boot);
+        log.record(200, EventKind::Orgasm);
+        let mut times = heapless::Vec::<u32, 8>::new();
+        telemetry.send_events(&log, &mut |frame: &[u8]| {
+            let (_, record) = decode_frame(&frame[1..frame.len() - 1]).unwrap();
+            times.push(record.time()).unwrap();
+        });
+        assert_eq!(&times[..], &[100, 200]);
+
+        log.record(300, EventKind::Boot);
+        times.clear();
+        telemetry.send_events(&log, &mut |frame: &[u8]| {
+            let (_, record) = decode_frame(&frame[1..frame.len() - 1]).unwrap();
+            times.push(record.time()).unwrap();
+        });
+        assert_eq!(&times[..], &[300]);
+    }
+
+    #[test]
+    fn samples_follow_interval() {
+        let mut telemetry = Telemetry::new();
+        assert!(telemetry.sample_due(0, 25));
+        assert!(!telemetry.sample_due(10, 25));
+        assert!(telemetry.sample_due(25, 25));
+        assert!(!telemetry.sample_due(100, 0));
+    }
+}
@@ -0,0 +1,194 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+
+use nogasm_rs::eventlog::EventKind;
+use nogasm_rs::telemetry::{decode_frame, Record};
+
+const COLUMNS: [&str; 18] = [
+    "seq",
+    "record",
+    "time",
+    "raw",
+    "filtered",
+    "baseline",
+    "area",
+    "rhythm",
+    "running",
+    "stimulating",
+    "cooldown",
+    "grace",
+    "edges",
+    "output",
+    "base",
+    "event",
+    "a",
+    "b",
+];
+
+enum Value {
+    Num(u32),
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    fn csv(&self) -> String {
+        match self {
+            Value::Num(n) => n.to_string(),
+            Value::Bool(b) => (*b as u8).to_string(),
+            Value::Str(s) => s.clone(),
+        }
+    }
+
+    fn json(&self) -> String {
+        match self {
+            Value::Num(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Str(s) => format!("\"{}\"", s.escape_default()),
+        }
+    }
+}
+
+fn fields(seq: u8, record: &Record) -> Vec<(&'static str, Value)> {
+    use Value::*;
+    let mut fields = vec![
+        ("seq", Num(seq as u32)),
+        ("record", Str(record.name().to_string())),
+        ("time", Num(record.time())),
+    ];
+    match *record {
+        Record::Sample {
+            raw,
+            filtered,
+            baseline,
+            area,
+            rhythm,
+            ..
+        } => fields.extend([
+            ("raw", Num(raw)),
+            ("filtered", Num(filtered)),
+            ("baseline", Num(baseline)),
+            ("area", Num(area)),
+            ("rhythm", Num(rhythm)),
+        ]),
+        Record::Detector {
+            running,
+            stimulating,
+            cooldown,
+            grace,
+            edges,
+            ..
+        } => fields.extend([
+            ("running", Bool(running)),
+            ("stimulating", Bool(stimulating)),
+            ("cooldown", Num(cooldown)),
+            ("grace", Num(grace)),
+            ("edges", Num(edges)),
+        ]),
+        Record::Intensity { output, base, .. } => {
+            fields.extend([("output", Num(output as u32)), ("base", Num(base as u32))])
+        }
+        Record::Event { code, a, b, .. } => match record.event_kind() {
+            Some(EventKind::SettingChanged { setting, value }) => fields.extend([
+                ("event", Str("setting_changed".to_string())),
+                ("a", Str(setting.name().to_string())),
+                ("b", Num(value)),
+            ]),
+            Some(kind) => fields.extend([
+                ("event", Str(kind.name().to_string())),
+                ("a", Num(a)),
+                ("b", Num(b)),
+            ]),
+            None => fields.extend([
+                ("event", Str(format!("unknown_{}", code))),
+                ("a", Num(a)),
+                ("b", Num(b)),
+            ]),
+        },
+    }
+    fields
+}
+
+fn write_csv<W: Write>(w: &mut W, fields: &[(&'static str, Value)]) -> io::Result<()> {
+    let line: Vec<String> = COLUMNS
+        .iter()
+        .map(
+            |column| match fields.iter().find(|(name, _)| name == column) {
+                Some((_, value)) => value.csv(),
+                None => String::new(),
+            },
+        )
+        .collect();
+    writeln!(w, "{}", line.join(","))
+}
+
+fn write_json<W: Write>(w: &mut W, fields: &[(&'static str, Value)]) -> io::Result<()> {
+    let pairs: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("\"{}\":{}", name, value.json()))
+        .collect();
+    writeln!(w, "{{{}}}", pairs.join(","))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: nogasm-decode [--json] [FILE]");
+    eprintln!("Decodes nogasm telemetry frames from FILE or stdin into CSV or JSON lines");
+    process::exit(2);
+}
+
+fn main() -> io::Result<()> {
+    let mut json = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--csv" => json = false,
+            "-h" | "--help" => usage(),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let input: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if !json {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+    }
+
+    let (mut decoded, mut invalid, mut dropped) = (0u32, 0u32, 0u32);
+    let mut last_seq: Option<u8> = None;
+    for chunk in input.split(0) {
+        let chunk = chunk?;
+        /* Empty chunks are back to back delimiters, anything else that does not decode is text output or a damaged frame */
+        if chunk.is_empty() {
+            continue;
+        }
+        let Some((seq, record)) = decode_frame(&chunk) else {
+            invalid += 1;
+            continue;
+        };
+        if let Some(last) = last_seq {
+            dropped += seq.wrapping_sub(last).wrapping_sub(1) as u32;
+        }
+        last_seq = Some(seq);
+        decoded += 1;
+
+        let fields = fields(seq, &record);
+        match json {
+            true => write_json(&mut out, &fields)?,
+            false => write_csv(&mut out, &fields)?,
+        }
+    }
+    out.flush()?;
+    eprintln!(
+        "{} frames decoded, {} invalid chunks, {} frames dropped",
+        decoded, invalid, dropped
+    );
+    Ok(())
+}
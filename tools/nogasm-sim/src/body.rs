@@ -0,0 +1,98 @@
+use nogasm_rs::rng::Rng;
+use nogasm_rs::state::MAX_INTENSITY;
+
+/* Pressure of the sensor at rest outside and the rise once it is inserted */
+const AMBIENT: f32 = 1_000_000.0;
+const INSERTED: f32 = 200_000.0;
+const NOISE: u32 = 2_000;
+
+/* Arousal per second at full output, and the part of it lost per second */
+const RISE: f32 = 0.06;
+const DECAY: f32 = 0.03;
+/* Clench height at full arousal, it grows with the square of arousal so clenches reach
+ * the default sensitivity from about half way */
+const CLENCH_HEIGHT: f32 = 80_000.0;
+const CLENCH_MS: u32 = 400;
+/* Contractions once arousal tops out, then it drops back down */
+const ORGASM_CONTRACTIONS: u32 = 8;
+const ORGASM_INTERVAL_MS: u32 = 800;
+const AFTERGLOW: f32 = 0.2;
+
+/* A made up wearer: arousal follows the toy's output, clenches get stronger and more
+ * frequent with it and a peak ends in a series of contractions */
+pub struct Body {
+    pub arousal: f32,
+    pub inserted: bool,
+    last_time: u32,
+    /* Start and height of the current clench */
+    clench: Option<(u32, f32)>,
+    contractions: u32,
+    rng: Rng,
+}
+
+impl Body {
+    pub fn new(seed: u32) -> Body {
+        Body {
+            arousal: 0.0,
+            inserted: true,
+            last_time: 0,
+            clench: None,
+            contractions: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub fn is_climaxing(&self) -> bool {
+        self.contractions > 0
+    }
+
+    /* Raw sensor value at `time` while the toy runs at `output` */
+    pub fn sample(&mut self, time: u32, output: u8) -> u32 {
+        let dt = time.wrapping_sub(self.last_time) as f32 / 1000.0;
+        self.last_time = time;
+
+        if self.inserted {
+            let drive = output as f32 / MAX_INTENSITY as f32;
+            self.arousal += (RISE * drive - DECAY * self.arousal) * dt;
+        } else {
+            self.arousal -= DECAY * self.arousal * dt;
+        }
+        self.arousal = self.arousal.clamp(0.0, 1.0);
+
+        if self.arousal >= 1.0 && self.contractions == 0 {
+            self.contractions = ORGASM_CONTRACTIONS;
+        }
+        if self.clench.is_none() && self.inserted {
+            if self.contractions > 0 {
+                self.contractions -= 1;
+                if self.contractions == 0 {
+                    self.arousal = AFTERGLOW;
+                }
+                self.clench = Some((time + ORGASM_INTERVAL_MS - CLENCH_MS, CLENCH_HEIGHT));
+            } else {
+                /* About arousal * 1.5 clenches per second */
+                let per_mille = (self.arousal * 1.5 * dt * 1000.0) as u32;
+                if self.rng.chance(per_mille) {
+                    let height = CLENCH_HEIGHT * self.arousal * self.arousal;
+                    self.clench = Some((time, height));
+                }
+            }
+        }
+
+        let mut pressure = AMBIENT;
+        if self.inserted {
+            pressure += INSERTED;
+        }
+        if let Some((start, height)) = self.clench {
+            let phase = time.wrapping_sub(start) as i32;
+            /* Contractions are spaced out by starting them in the future */
+            if phase >= CLENCH_MS as i32 {
+                self.clench = None;
+            } else if phase >= 0 {
+                let shape = (phase as f32 / CLENCH_MS as f32 * std::f32::consts::PI).sin();
+                pressure += height * shape;
+            }
+        }
+        pressure as u32 + self.rng.range(0, NOISE)
+    }
+}
@@ -0,0 +1,330 @@
+mod body;
+mod term;
+
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::rc::Rc;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::v2::InputPin;
+use nogasm_rs::command::{dispatch, Command};
+use nogasm_rs::controller::Controller;
+use nogasm_rs::display::{Layout, Ui};
+use nogasm_rs::framebuffer::FrameBuffer;
+use nogasm_rs::menu::Menu;
+use nogasm_rs::state::{State, MAX_INTENSITY};
+use nogasm_rs::switch::{DebouncedSwitch, SwitchTimings};
+use nogasm_rs::telemetry::{decode_frame, Record};
+
+use body::Body;
+use term::Key;
+
+/* The H710 runs at 40 Hz */
+const SAMPLE_MS: u32 = 25;
+const FRAME_MS: u32 = 50;
+/* A key press only comes as one byte, so a click holds the switch this long */
+const CLICK_MS: u64 = 80;
+const BAR_WIDTH: usize = 40;
+
+/* The encoder switch, pressed while the cell is true */
+struct SimPin(Rc<Cell<bool>>);
+
+impl InputPin for SimPin {
+    type Error = Infallible;
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(!self.0.get())
+    }
+    fn is_low(&self) -> Result<bool, Infallible> {
+        Ok(self.0.get())
+    }
+}
+
+enum Source {
+    Body(Body),
+    /* Raw samples of a telemetry capture as (time, raw), played in a loop */
+    Replay {
+        samples: Vec<(u32, u32)>,
+        next: usize,
+        offset: u32,
+    },
+}
+
+impl Source {
+    fn replay(path: &str) -> io::Result<Source> {
+        let data = fs::read(path)?;
+        let mut samples: Vec<(u32, u32)> = data
+            .split(|&b| b == 0)
+            .filter_map(|chunk| match decode_frame(chunk)?.1 {
+                Record::Sample { time, raw, .. } => Some((time, raw)),
+                _ => None,
+            })
+            .collect();
+        let Some(&(start, _)) = samples.first() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no samples in capture, was telemetry on?",
+            ));
+        };
+        for sample in samples.iter_mut() {
+            sample.0 = sample.0.wrapping_sub(start);
+        }
+        Ok(Source::Replay {
+            samples,
+            next: 0,
+            offset: 0,
+        })
+    }
+
+    /* The next sensor value once one is due */
+    fn poll(&mut self, time: u32, last: u32, output: u8) -> Option<u32> {
+        match self {
+            Source::Body(body) => match time.wrapping_sub(last) >= SAMPLE_MS {
+                true => Some(body.sample(time, output)),
+                false => None,
+            },
+            Source::Replay {
+                samples,
+                next,
+                offset,
+            } => {
+                if *next == samples.len() {
+                    *next = 0;
+                    *offset = time;
+                }
+                let (at, raw) = samples[*next];
+                if time.wrapping_sub(*offset) < at {
+                    return None;
+                }
+                *next += 1;
+                Some(raw)
+            }
+        }
+    }
+}
+
+struct Sim {
+    controller: Controller<SimPin>,
+    pressed: Rc<Cell<bool>>,
+    /* When a click releases the switch, None while it is held down with 'h' */
+    release_at: Option<u64>,
+    source: Source,
+    last_sample: u32,
+}
+
+impl Sim {
+    fn new(source: Source, rows: usize) -> Sim {
+        let pressed = Rc::new(Cell::new(false));
+        let mut state = State::new();
+        state.tease.seed(1);
+        state.set_ble_connected(true);
+        state.set_ble_name("Simulator");
+        let switch = DebouncedSwitch::new(SimPin(pressed.clone()), SwitchTimings::default());
+        Sim {
+            controller: Controller::new(state, Menu::with_rows(rows), switch),
+            pressed,
+            release_at: Some(0),
+            source,
+            last_sample: 0,
+        }
+    }
+
+    fn key(&mut self, key: Key, now_us: u64) {
+        let detents = match key {
+            Key::Right => 1,
+            Key::Left => -1,
+            Key::Char(b' ') => {
+                self.pressed.set(true);
+                self.release_at = Some(now_us + CLICK_MS * 1_000);
+                return;
+            }
+            Key::Char(b'h') => {
+                let held = !self.pressed.get();
+                self.pressed.set(held);
+                self.release_at = match held {
+                    true => None,
+                    false => Some(now_us),
+                };
+                return;
+            }
+            Key::Char(b'i') => {
+                if let Source::Body(body) = &mut self.source {
+                    body.inserted = !body.inserted;
+                }
+                return;
+            }
+            Key::Char(b'c') => {
+                dispatch(&mut self.controller.state, Command::Calibrate);
+                return;
+            }
+            Key::Char(_) => return,
+        };
+        self.controller.turn(detents, (now_us / 1_000) as u32);
+    }
+
+    /* One pass of the device's main loop, without the serial port and storage */
+    fn step(&mut self, now_us: u64) {
+        if matches!(self.release_at, Some(at) if at <= now_us) {
+            self.pressed.set(false);
+        }
+        let controller = &mut self.controller;
+        controller.poll_switch(now_us);
+        let time = (now_us / 1_000) as u32;
+        controller.update(time);
+
+        let output = controller.output();
+        if !controller.wants_sensor() {
+            return;
+        }
+        if let Some(val) = self.source.poll(time, self.last_sample, output) {
+            self.last_sample = time;
+            controller.sensor(Some(val));
+        }
+    }
+
+    fn draw<const W: usize, const H: usize>(
+        &self,
+        out: &mut impl Write,
+        frame: &mut FrameBuffer<W, H>,
+    ) -> io::Result<()> {
+        frame.clear();
+        Ui::new(frame)
+            .draw(&self.controller.menu, &self.controller.state)
+            .ok();
+
+        write!(out, "\x1b[H")?;
+        term::draw_frame(out, frame)?;
+        let state = &self.controller.state;
+        term::draw_bar(
+            out,
+            "Output",
+            state.get_cur_intensity() as u32,
+            MAX_INTENSITY as u32,
+            BAR_WIDTH,
+        )?;
+        term::draw_bar(
+            out,
+            "Area",
+            self.controller.history.get_area(),
+            state.peak_area_threshold,
+            BAR_WIDTH,
+        )?;
+        /* Full once the rhythm would call an edge, when the rhythm warning is on */
+        term::draw_bar(
+            out,
+            "Rhythm",
+            self.controller.history.get_rhythm(),
+            state.rhythm_thresh,
+            BAR_WIDTH,
+        )?;
+        if let Source::Body(body) = &self.source {
+            term::draw_bar(
+                out,
+                "Arousal",
+                (body.arousal * 100.0) as u32,
+                100,
+                BAR_WIDTH,
+            )?;
+            write!(
+                out,
+                "{}{}\x1b[K\r\n",
+                match body.inserted {
+                    true => "inserted",
+                    false => "removed",
+                },
+                match body.is_climaxing() {
+                    true => ", climaxing",
+                    false => "",
+                }
+            )?;
+        }
+        write!(
+            out,
+            "{:.1}s  edges {}  orgasms {}\x1b[K\r\n",
+            state.cur_time_ms as f32 / 1000.0,
+            state.stats.edges,
+            state.stats.orgasms
+        )?;
+        write!(
+            out,
+            "arrows: turn  space: click  h: hold switch  i: insert/remove  c: calibrate  q: quit\x1b[K\r\n"
+        )?;
+        out.flush()
+    }
+}
+
+fn run<const W: usize, const H: usize>(source: Source) -> io::Result<()> {
+    let layout = Layout {
+        width: W as i32,
+        height: H as i32,
+    };
+    let mut sim = Sim::new(source, layout.rows());
+    let mut frame = FrameBuffer::<W, H>::new();
+    let _raw = term::RawMode::enter()?;
+    let keys = term::keys();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let start = Instant::now();
+    let mut next_frame = 0;
+    loop {
+        let now_us = start.elapsed().as_micros() as u64;
+        loop {
+            match keys.try_recv() {
+                /* q or Ctrl-C */
+                Ok(Key::Char(b'q' | 3)) | Err(TryRecvError::Disconnected) => return Ok(()),
+                Ok(key) => sim.key(key, now_us),
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        sim.step(now_us);
+        let time = sim.controller.state.cur_time_ms;
+        if time >= next_frame {
+            next_frame = time + FRAME_MS;
+            sim.draw(&mut out, &mut frame)?;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: nogasm-sim [--tall] [--seed N] [CAPTURE]");
+    eprintln!("Runs the nogasm menu, detector and display in the terminal, with the sensor");
+    eprintln!("replayed from a telemetry CAPTURE or simulated by a body model");
+    eprintln!("  --tall    use a 128x64 panel instead of 128x32");
+    eprintln!("  --seed N  seed of the body model");
+    process::exit(2);
+}
+
+fn main() -> io::Result<()> {
+    let mut tall = false;
+    let mut seed = 1;
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tall" => tall = true,
+            "--seed" => match args.next().and_then(|seed| seed.parse().ok()) {
+                Some(value) => seed = value,
+                None => usage(),
+            },
+            "-h" | "--help" => usage(),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let source = match path {
+        Some(path) => Source::replay(&path)?,
+        None => Source::Body(Body::new(seed)),
+    };
+    match tall {
+        true => run::<128, 64>(source),
+        false => run::<128, 32>(source),
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::{self, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use nogasm_rs::framebuffer::FrameBuffer;
+
+pub enum Key {
+    Left,
+    Right,
+    Char(u8),
+}
+
+/* Puts the terminal into raw mode with stty and restores it when dropped */
+pub struct RawMode {
+    saved: String,
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed, not a terminal?"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl RawMode {
+    pub fn enter() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        /* Hide the cursor and clear the screen */
+        print!("\x1b[?25l\x1b[2J");
+        io::stdout().flush()?;
+        Ok(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]).ok();
+        print!("\x1b[?25h\r\n");
+        io::stdout().flush().ok();
+    }
+}
+
+/* Reads keys on a thread, so the simulation keeps running while none are pressed */
+pub fn keys() -> Receiver<Key> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = BufReader::new(io::stdin()).bytes().map_while(Result::ok);
+        while let Some(byte) = bytes.next() {
+            let key = match byte {
+                /* Arrow keys are ESC [ A to D, up and right turn clockwise */
+                0x1b => match (bytes.next(), bytes.next()) {
+                    (Some(b'['), Some(b'A' | b'C')) => Key::Right,
+                    (Some(b'['), Some(b'B' | b'D')) => Key::Left,
+                    _ => continue,
+                },
+                byte => Key::Char(byte),
+            };
+            if tx.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/* Two rows of pixels per line of half block characters */
+pub fn draw_frame<const W: usize, const H: usize>(
+    out: &mut impl Write,
+    frame: &FrameBuffer<W, H>,
+) -> io::Result<()> {
+    let border = "─".repeat(W);
+    write!(out, "┌{}┐\r\n", border)?;
+    for y in (0..H).step_by(2) {
+        let mut line = String::with_capacity(W * 3);
+        for x in 0..W {
+            let bottom = y + 1 < H && frame.pixel(x, y + 1);
+            line.push(match (frame.pixel(x, y), bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        write!(out, "│{}│\r\n", line)?;
+    }
+    write!(out, "└{}┘\r\n", border)
+}
+
+pub fn draw_bar(
+    out: &mut impl Write,
+    label: &str,
+    value: u32,
+    max: u32,
+    width: usize,
+) -> io::Result<()> {
+    let filled = (value.min(max) as usize * width) / max.max(1) as usize;
+    write!(
+        out,
+        "{:<8}[{}{}] {}/{}\x1b[K\r\n",
+        label,
+        "█".repeat(filled),
+        " ".repeat(width - filled),
+        value,
+        max
+    )
+}